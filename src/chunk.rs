@@ -29,6 +29,22 @@ pub const pHYs: ChunkType = [b'p', b'H', b'Y', b's'];
 pub const cHRM: ChunkType = [b'c', b'H', b'R', b'M'];
 /// Source system's gamma value
 pub const gAMA: ChunkType = [b'g', b'A', b'M', b'A'];
+/// Embedded ICC profile
+pub const iCCP: ChunkType = [b'i', b'C', b'C', b'P'];
+/// Standard RGB colour space
+pub const sRGB: ChunkType = [b's', b'R', b'G', b'B'];
+/// Significant bits
+pub const sBIT: ChunkType = [b's', b'B', b'I', b'T'];
+/// Palette histogram
+pub const hIST: ChunkType = [b'h', b'I', b'S', b'T'];
+/// Suggested palette
+pub const sPLT: ChunkType = [b's', b'P', b'L', b'T'];
+/// Textual data
+pub const tEXt: ChunkType = [b't', b'E', b'X', b't'];
+/// Compressed textual data
+pub const zTXt: ChunkType = [b'z', b'T', b'X', b't'];
+/// International textual data
+pub const iTXt: ChunkType = [b'i', b'T', b'X', b't'];
 
 // -- Extension chunks --
 
@@ -38,6 +54,22 @@ pub const acTL: ChunkType = [b'a', b'c', b'T', b'L'];
 pub const fcTL: ChunkType = [b'f', b'c', b'T', b'L'];
 /// Frame data
 pub const fdAT: ChunkType = [b'f', b'd', b'A', b'T'];
+/// Exif metadata
+pub const eXIf: ChunkType = [b'e', b'X', b'I', b'f'];
+/// Coding-independent code points for video signal type identification
+pub const cICP: ChunkType = [b'c', b'I', b'C', b'P'];
+/// Mastering display color volume
+pub const mDCv: ChunkType = [b'm', b'D', b'C', b'v'];
+/// Content light level information
+pub const cLLi: ChunkType = [b'c', b'L', b'L', b'i'];
+/// Image offset
+pub const oFFs: ChunkType = [b'o', b'F', b'F', b's'];
+/// Calibration of pixel values
+pub const pCAL: ChunkType = [b'p', b'C', b'A', b'L'];
+/// Physical scale of image subject
+pub const sCAL: ChunkType = [b's', b'C', b'A', b'L'];
+/// Indicator of stereo image
+pub const sTER: ChunkType = [b's', b'T', b'E', b'R'];
 
 // -- Chunk type determination --
 
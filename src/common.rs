@@ -1,6 +1,8 @@
 //! Common types shared between the encoder and decoder
 use crate::filter;
+use crate::text_metadata::{ITXtChunk, TEXtChunk, ZTXtChunk};
 
+use std::time::{SystemTime, UNIX_EPOCH};
 use std::{convert::TryFrom, fmt};
 
 /// Describes the layout of samples in a pixel
@@ -118,7 +120,7 @@ impl BitDepth {
 }
 
 /// Pixel dimensions information
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct PixelDimensions {
     /// Pixels per unit, X axis
     pub xppu: u32,
@@ -128,6 +130,47 @@ pub struct PixelDimensions {
     pub unit: Unit,
 }
 
+impl PixelDimensions {
+    const METERS_PER_INCH: f64 = 0.0254;
+
+    /// The pixels per meter of a resolution in dots per inch, rounded to whole pixels.
+    ///
+    /// Negative resolutions are clamped to zero and overly large ones to the largest value of a
+    /// pHYs chunk, 2^31 - 1.
+    pub fn from_dpi(x_dpi: f64, y_dpi: f64) -> PixelDimensions {
+        let ppm = |dpi: f64| {
+            let ppm = (dpi / Self::METERS_PER_INCH).round();
+            ppm.max(0.0).min(f64::from(i32::max_value())) as u32
+        };
+        PixelDimensions {
+            xppu: ppm(x_dpi),
+            yppu: ppm(y_dpi),
+            unit: Unit::Meter,
+        }
+    }
+
+    /// The resolution in dots per inch, or `None` if the unit is not specified and the values
+    /// only describe the aspect ratio of the pixels.
+    pub fn dpi(&self) -> Option<(f64, f64)> {
+        match self.unit {
+            Unit::Meter => Some((
+                f64::from(self.xppu) * Self::METERS_PER_INCH,
+                f64::from(self.yppu) * Self::METERS_PER_INCH,
+            )),
+            Unit::Unspecified => None,
+        }
+    }
+}
+
+/// An embedded ICC profile, stored in an `iCCP` chunk
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IccProfile {
+    /// The profile name, 1-79 Latin-1 characters like the keyword of a text chunk
+    pub name: String,
+    /// The decompressed ICC profile data
+    pub profile: Vec<u8>,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 /// Physical unit of the pixel dimensions
@@ -147,6 +190,252 @@ impl Unit {
     }
 }
 
+/// The rendering intent of an image in the sRGB color space, stored in an `sRGB` chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum SrgbRenderingIntent {
+    /// For images preferring good adaptation to the output device gamut at the expense of
+    /// colorimetric accuracy, such as photographs.
+    Perceptual = 0,
+    /// For images requiring colour appearance matching (relative to the output device white
+    /// point), such as logos.
+    RelativeColorimetric = 1,
+    /// For images preferring preservation of saturation at the expense of hue and lightness,
+    /// such as charts and graphs.
+    Saturation = 2,
+    /// For images requiring preservation of absolute colorimetry, such as previews of images
+    /// destined for a different output device (proofs).
+    AbsoluteColorimetric = 3,
+}
+
+impl SrgbRenderingIntent {
+    /// u8 -> Self. Temporary solution until Rust provides a canonical one.
+    pub fn from_u8(n: u8) -> Option<SrgbRenderingIntent> {
+        match n {
+            0 => Some(SrgbRenderingIntent::Perceptual),
+            1 => Some(SrgbRenderingIntent::RelativeColorimetric),
+            2 => Some(SrgbRenderingIntent::Saturation),
+            3 => Some(SrgbRenderingIntent::AbsoluteColorimetric),
+            _ => None,
+        }
+    }
+}
+
+/// The preferred background color of an image, stored in a `bKGD` chunk
+///
+/// Gray and RGB samples use the bit depth of the image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackgroundColor {
+    /// An index into the palette of an indexed image
+    Indexed(u8),
+    /// A gray level for grayscale images with or without alpha
+    Gray(u16),
+    /// Red, green and blue samples for truecolor images with or without alpha
+    Rgb(u16, u16, u16),
+}
+
+impl BackgroundColor {
+    /// Parse the contents of a bKGD chunk of an image with the color type `color_type`.
+    pub(crate) fn from_bytes(color_type: ColorType, bytes: &[u8]) -> Option<BackgroundColor> {
+        let sample = |i: usize| u16::from_be_bytes([bytes[2 * i], bytes[2 * i + 1]]);
+        match (color_type, bytes.len()) {
+            (ColorType::Indexed, 1) => Some(BackgroundColor::Indexed(bytes[0])),
+            (ColorType::Grayscale, 2) | (ColorType::GrayscaleAlpha, 2) => {
+                Some(BackgroundColor::Gray(sample(0)))
+            }
+            (ColorType::RGB, 6) | (ColorType::RGBA, 6) => {
+                Some(BackgroundColor::Rgb(sample(0), sample(1), sample(2)))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The time of the last modification of the image, in UTC, stored in a `tIME` chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Time {
+    /// The complete year, such as 1995
+    pub year: u16,
+    /// 1-12
+    pub month: u8,
+    /// 1-31, depending on the month
+    pub day: u8,
+    /// 0-23
+    pub hour: u8,
+    /// 0-59
+    pub minute: u8,
+    /// 0-60, to allow for leap seconds
+    pub second: u8,
+}
+
+impl Time {
+    /// Create a timestamp, returning `None` if any field is out of range.
+    pub fn new(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> Option<Time> {
+        let time = Time {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        };
+        if time.is_valid() {
+            Some(time)
+        } else {
+            None
+        }
+    }
+
+    /// The UTC time of `time`, or `None` if it is before 1970 or after the year 65535.
+    pub fn from_system_time(time: SystemTime) -> Option<Time> {
+        let seconds = time.duration_since(UNIX_EPOCH).ok()?.as_secs();
+        let (days, seconds) = (seconds / 86400, seconds % 86400);
+        // Convert the days since 1970-01-01 to a date of the proleptic Gregorian calendar, with
+        // years starting in March so that leap days come last.
+        let days = days + 719_468;
+        let era = days / 146_097;
+        let day_of_era = days % 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_from_march = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+        let month = if month_from_march < 10 {
+            month_from_march + 3
+        } else {
+            month_from_march - 9
+        };
+        let year = era * 400 + year_of_era + if month <= 2 { 1 } else { 0 };
+        Some(Time {
+            year: u16::try_from(year).ok()?,
+            month: month as u8,
+            day: day as u8,
+            hour: (seconds / 3600) as u8,
+            minute: (seconds / 60 % 60) as u8,
+            second: (seconds % 60) as u8,
+        })
+    }
+
+    /// Checks that all fields are within their range, including the day of the month.
+    pub fn is_valid(&self) -> bool {
+        let leap_year = match (self.year % 4, self.year % 100, self.year % 400) {
+            (_, _, 0) => true,
+            (_, 0, _) => false,
+            (remainder, _, _) => remainder == 0,
+        };
+        let days = match self.month {
+            2 if leap_year => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            _ => 31,
+        };
+        self.month >= 1
+            && self.month <= 12
+            && self.day >= 1
+            && self.day <= days
+            && self.hour <= 23
+            && self.minute <= 59
+            && self.second <= 60
+    }
+}
+
+/// The orientation of the stored image relative to the displayed one, from the Exif metadata
+///
+/// The names give the sides of the displayed image at which the first row and the first column
+/// of the stored image are placed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Orientation {
+    /// The image is stored as displayed.
+    TopLeft = 1,
+    /// The image is flipped horizontally.
+    TopRight = 2,
+    /// The image is rotated by 180°.
+    BottomRight = 3,
+    /// The image is flipped vertically.
+    BottomLeft = 4,
+    /// The image is flipped along its main diagonal, rows become columns.
+    LeftTop = 5,
+    /// The image must be rotated by 90° clockwise for display.
+    RightTop = 6,
+    /// The image is flipped along its anti-diagonal.
+    RightBottom = 7,
+    /// The image must be rotated by 90° counterclockwise for display.
+    LeftBottom = 8,
+}
+
+impl Orientation {
+    /// u8 -> Self. Temporary solution until Rust provides a canonical one.
+    pub fn from_u8(n: u8) -> Option<Orientation> {
+        match n {
+            1 => Some(Orientation::TopLeft),
+            2 => Some(Orientation::TopRight),
+            3 => Some(Orientation::BottomRight),
+            4 => Some(Orientation::BottomLeft),
+            5 => Some(Orientation::LeftTop),
+            6 => Some(Orientation::RightTop),
+            7 => Some(Orientation::RightBottom),
+            8 => Some(Orientation::LeftBottom),
+            _ => None,
+        }
+    }
+
+    /// Read the orientation tag from the first image file directory of Exif data.
+    pub(crate) fn from_exif(exif: &[u8]) -> Option<Orientation> {
+        const ORIENTATION_TAG: u16 = 0x0112;
+        const SHORT_TYPE: u16 = 3;
+        // Tolerate the header of Exif data in JPEG files.
+        let exif = if exif.starts_with(b"Exif\0\0") {
+            &exif[6..]
+        } else {
+            exif
+        };
+        let header = exif.get(..4)?;
+        let big_endian = if header == b"MM\0*" {
+            true
+        } else if header == b"II*\0" {
+            false
+        } else {
+            return None;
+        };
+        let u16_at = |offset: usize| {
+            let bytes = exif.get(offset..offset.checked_add(2)?)?;
+            let bytes = [bytes[0], bytes[1]];
+            Some(if big_endian {
+                u16::from_be_bytes(bytes)
+            } else {
+                u16::from_le_bytes(bytes)
+            })
+        };
+        let u32_at = |offset: usize| {
+            let bytes = exif.get(offset..offset.checked_add(4)?)?;
+            let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+            Some(if big_endian {
+                u32::from_be_bytes(bytes)
+            } else {
+                u32::from_le_bytes(bytes)
+            })
+        };
+
+        let directory = usize::try_from(u32_at(4)?).ok()?;
+        let entries = u16_at(directory)?;
+        for entry in (0..usize::from(entries)).map(|i| directory + 2 + 12 * i) {
+            if u16_at(entry)? == ORIENTATION_TAG {
+                if u16_at(entry + 2)? != SHORT_TYPE || u32_at(entry + 4)? != 1 {
+                    return None;
+                }
+                return Orientation::from_u8(u8::try_from(u16_at(entry + 8)?).ok()?);
+            }
+        }
+        None
+    }
+
+    /// Whether the rows of the stored image become the columns of the displayed one.
+    pub(crate) fn transposes(self) -> bool {
+        self as u8 >= Orientation::LeftTop as u8
+    }
+}
+
 /// How to reset buffer of an animated png (APNG) at the end of a frame.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
@@ -296,6 +585,9 @@ pub struct ScaledFloat(u32);
 impl ScaledFloat {
     const SCALING: f32 = 100000.0;
 
+    /// The gamma of the sRGB color space, recommended for the gAMA chunk of sRGB images.
+    pub const SRGB_GAMMA: ScaledFloat = ScaledFloat(45455);
+
     /// Gets whether the value is within the clamped range of this type.
     pub fn in_range(value: f32) -> bool {
         value >= 0.0 && (value * Self::SCALING).floor() <= std::u32::MAX as f32
@@ -350,6 +642,14 @@ pub struct SourceChromaticities {
 }
 
 impl SourceChromaticities {
+    /// The chromaticities of the sRGB color space, recommended for the cHRM chunk of sRGB images.
+    pub const SRGB: SourceChromaticities = SourceChromaticities {
+        white: (ScaledFloat(31270), ScaledFloat(32900)),
+        red: (ScaledFloat(64000), ScaledFloat(33000)),
+        green: (ScaledFloat(30000), ScaledFloat(60000)),
+        blue: (ScaledFloat(15000), ScaledFloat(6000)),
+    };
+
     pub fn new(white: (f32, f32), red: (f32, f32), green: (f32, f32), blue: (f32, f32)) -> Self {
         SourceChromaticities {
             white: (ScaledFloat::new(white.0), ScaledFloat::new(white.1)),
@@ -360,6 +660,251 @@ impl SourceChromaticities {
     }
 }
 
+/// An entry of a suggested palette
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SuggestedPaletteEntry {
+    pub red: u16,
+    pub green: u16,
+    pub blue: u16,
+    /// Opacity, 0 is fully transparent
+    pub alpha: u16,
+    /// The relative usage of the color, proportional to the fraction of pixels close to it
+    pub frequency: u16,
+}
+
+/// A palette suggested for displays with a limited number of colors, stored in an `sPLT` chunk
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SuggestedPalette {
+    /// The palette name, 1-79 Latin-1 characters like the keyword of a text chunk
+    pub name: String,
+    /// `Eight` or `Sixteen`, the depth of the color and alpha samples of the entries
+    pub sample_depth: BitDepth,
+    pub entries: Vec<SuggestedPaletteEntry>,
+}
+
+/// Coding-independent code points of ITU-T H.273 identifying the color space, stored in a `cICP`
+/// chunk
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CodingIndependentCodePoints {
+    /// The color primaries, such as 1 for BT.709 or 9 for BT.2020
+    pub color_primaries: u8,
+    /// The transfer function, such as 16 for PQ or 18 for HLG
+    pub transfer_function: u8,
+    /// The matrix coefficients, always 0 since PNG images are RGB
+    pub matrix_coefficients: u8,
+    /// Whether samples use the full range rather than the narrow range of video signals
+    pub is_video_full_range_image: bool,
+}
+
+/// The color volume of the display used for mastering, stored in an `mDCv` chunk
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MasteringDisplayColorVolume {
+    /// Chromaticity (x, y) of the white point in units of 0.00002
+    pub white: (u16, u16),
+    /// Chromaticity (x, y) of the red primary in units of 0.00002
+    pub red: (u16, u16),
+    /// Chromaticity (x, y) of the green primary in units of 0.00002
+    pub green: (u16, u16),
+    /// Chromaticity (x, y) of the blue primary in units of 0.00002
+    pub blue: (u16, u16),
+    /// Maximum luminance in units of 0.0001 cd/m²
+    pub max_luminance: u32,
+    /// Minimum luminance in units of 0.0001 cd/m²
+    pub min_luminance: u32,
+}
+
+/// The light level of the content, stored in a `cLLi` chunk
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ContentLightLevelInfo {
+    /// Maximum light level of any pixel (MaxCLL) in units of 0.0001 cd/m²
+    pub max_content_light_level: u32,
+    /// Maximum average light level of any frame (MaxFALL) in units of 0.0001 cd/m²
+    pub max_frame_average_light_level: u32,
+}
+
+/// The unit of an image offset
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum OffsetUnit {
+    Pixel = 0,
+    Micrometer = 1,
+}
+
+impl OffsetUnit {
+    /// u8 -> Self. Temporary solution until Rust provides a canonical one.
+    pub fn from_u8(n: u8) -> Option<OffsetUnit> {
+        match n {
+            0 => Some(OffsetUnit::Pixel),
+            1 => Some(OffsetUnit::Micrometer),
+            _ => None,
+        }
+    }
+}
+
+/// The position of the image on a page or within a larger image, stored in an `oFFs` chunk
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ImageOffset {
+    /// Offset of the left edge, positive to the right
+    pub x: i32,
+    /// Offset of the top edge, positive downwards
+    pub y: i32,
+    pub unit: OffsetUnit,
+}
+
+/// The equation mapping samples to physical values in a `pCAL` chunk
+///
+/// `x` is the original sample and `d` the difference of the largest and smallest original sample.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum EquationType {
+    /// `p0 + p1 * x / d`
+    Linear = 0,
+    /// `p0 + p1 * e^(p2 * x / d)`
+    Exponential = 1,
+    /// `p0 + p1 * p2^(x / d)`
+    ArbitraryBaseExponential = 2,
+    /// `p0 + p1 * sinh(p2 * (x - p3) / d)`
+    Hyperbolic = 3,
+}
+
+impl EquationType {
+    /// u8 -> Self. Temporary solution until Rust provides a canonical one.
+    pub fn from_u8(n: u8) -> Option<EquationType> {
+        match n {
+            0 => Some(EquationType::Linear),
+            1 => Some(EquationType::Exponential),
+            2 => Some(EquationType::ArbitraryBaseExponential),
+            3 => Some(EquationType::Hyperbolic),
+            _ => None,
+        }
+    }
+
+    /// The number of parameters of the equation.
+    pub fn parameter_count(self) -> usize {
+        match self {
+            EquationType::Linear => 2,
+            EquationType::Exponential | EquationType::ArbitraryBaseExponential => 3,
+            EquationType::Hyperbolic => 4,
+        }
+    }
+}
+
+/// The calibration of the samples to physical values, stored in a `pCAL` chunk
+///
+/// A stored sample is first mapped linearly to an original sample between `original_zero` and
+/// `original_max`, which the equation then maps to the physical value.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PixelCalibration {
+    /// The calibration name, 1-79 Latin-1 characters like the keyword of a text chunk
+    pub name: String,
+    /// The original sample of the stored sample 0
+    pub original_zero: i32,
+    /// The original sample of the largest stored sample, different from `original_zero`
+    pub original_max: i32,
+    pub equation_type: EquationType,
+    /// The physical unit of the values, in Latin-1
+    pub unit: String,
+    /// The finite parameters `p0`, `p1`, ... of the equation
+    pub parameters: Vec<f64>,
+}
+
+impl PixelCalibration {
+    /// The physical value of a stored sample, where `max_stored` is the largest sample of the bit
+    /// depth or palette index of the image.
+    ///
+    /// Returns `None` if `max_stored` is zero or the calibration is invalid.
+    pub fn physical_value(&self, stored: u32, max_stored: u32) -> Option<f64> {
+        let range = f64::from(self.original_max) - f64::from(self.original_zero);
+        let p = &self.parameters;
+        if max_stored == 0 || range == 0.0 || p.len() != self.equation_type.parameter_count() {
+            return None;
+        }
+        // The integer mapping of the specification, rounding to the nearest original sample.
+        let scaled = f64::from(stored) * range + f64::from(max_stored / 2);
+        let original = (scaled / f64::from(max_stored)).floor() + f64::from(self.original_zero);
+        let x = original / range;
+        Some(match self.equation_type {
+            EquationType::Linear => p[0] + p[1] * x,
+            EquationType::Exponential => p[0] + p[1] * (p[2] * x).exp(),
+            EquationType::ArbitraryBaseExponential => p[0] + p[1] * p[2].powf(x),
+            EquationType::Hyperbolic => p[0] + p[1] * (p[2] * (x - p[3] / range)).sinh(),
+        })
+    }
+}
+
+/// The unit of a physical scale
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ScaleUnit {
+    Meter = 1,
+    Radian = 2,
+}
+
+impl ScaleUnit {
+    /// u8 -> Self. Temporary solution until Rust provides a canonical one.
+    pub fn from_u8(n: u8) -> Option<ScaleUnit> {
+        match n {
+            1 => Some(ScaleUnit::Meter),
+            2 => Some(ScaleUnit::Radian),
+            _ => None,
+        }
+    }
+}
+
+/// The physical size of the pixels of the image subject, stored in an `sCAL` chunk
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PhysicalScale {
+    pub unit: ScaleUnit,
+    /// The positive and finite width of a pixel
+    pub pixel_width: f64,
+    /// The positive and finite height of a pixel
+    pub pixel_height: f64,
+}
+
+/// The layout of a stereo image, stored in an `sTER` chunk
+///
+/// The image consists of the right-eye and left-eye images side by side, separated by up to 15
+/// columns of padding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum StereoLayout {
+    /// The right-eye image is on the left, for viewing with crossed eyes
+    CrossFuse = 0,
+    /// The left-eye image is on the left, for viewing with diverging eyes
+    DivergingFuse = 1,
+}
+
+/// Parses a floating point number in the ASCII format of the `pCAL` and `sCAL` chunks.
+pub(crate) fn parse_ascii_float(bytes: &[u8]) -> Option<f64> {
+    if !bytes
+        .iter()
+        .all(|b| b.is_ascii_digit() || b"+-.eE".contains(b))
+    {
+        return None;
+    }
+    let value: f64 = std::str::from_utf8(bytes).ok()?.parse().ok()?;
+    Some(value).filter(|value| value.is_finite())
+}
+
+/// Where an ancillary chunk appeared relative to the critical chunks
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChunkPosition {
+    /// Before the PLTE chunk, or before the image data of images without a palette
+    BeforePlte,
+    /// Between the PLTE chunk and the image data
+    BeforeIdat,
+    /// After the image data
+    AfterIdat,
+}
+
+/// An ancillary chunk not interpreted by this crate, kept with its position to be copied
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnknownChunk {
+    pub chunk_type: crate::chunk::ChunkType,
+    pub data: Vec<u8>,
+    pub position: ChunkPosition,
+}
+
 /// PNG info struct
 #[derive(Clone, Debug)]
 pub struct Info {
@@ -377,7 +922,51 @@ pub struct Info {
     pub animation_control: Option<AnimationControl>,
     pub compression: Compression,
     pub filter: filter::FilterType,
+    pub adaptive_filter: filter::AdaptiveFilterType,
     pub source_chromaticities: Option<SourceChromaticities>,
+    /// The embedded ICC profile
+    pub icc_profile: Option<IccProfile>,
+    /// The rendering intent of an image in the sRGB color space
+    pub srgb: Option<SrgbRenderingIntent>,
+    /// The number of significant bits of each channel of the original data, from the sBIT chunk.
+    ///
+    /// One entry for grayscale, two for grayscale with alpha, three for RGB and indexed images, the
+    /// latter referring to the palette, and four for RGBA images.
+    pub significant_bits: Option<Vec<u8>>,
+    /// The preferred background color
+    pub background_color: Option<BackgroundColor>,
+    /// The usage frequency of each palette entry from the hIST chunk
+    pub histogram: Option<Vec<u16>>,
+    /// sPLT chunks, in the order they were encountered
+    pub suggested_palettes: Vec<SuggestedPalette>,
+    /// The time of the last modification
+    pub last_modified: Option<Time>,
+    /// The raw Exif metadata of the eXIf chunk, starting with the TIFF header
+    pub exif_metadata: Option<Vec<u8>>,
+    /// The color space of the cICP chunk, which takes precedence over other color information
+    pub coding_independent_code_points: Option<CodingIndependentCodePoints>,
+    /// The mastering display of the mDCv chunk
+    pub mastering_display_color_volume: Option<MasteringDisplayColorVolume>,
+    /// The content light level of the cLLi chunk
+    pub content_light_level: Option<ContentLightLevelInfo>,
+    /// The position of the image from the oFFs chunk
+    pub offset: Option<ImageOffset>,
+    /// The calibration of the samples from the pCAL chunk
+    pub pixel_calibration: Option<PixelCalibration>,
+    /// The physical pixel size from the sCAL chunk
+    pub physical_scale: Option<PhysicalScale>,
+    /// The stereo layout from the sTER chunk
+    pub stereo_layout: Option<StereoLayout>,
+    /// tEXt chunks, in the order they were encountered
+    pub uncompressed_latin1_text: Vec<TEXtChunk>,
+    /// zTXt chunks, in the order they were encountered
+    pub compressed_latin1_text: Vec<ZTXtChunk>,
+    /// iTXt chunks, in the order they were encountered
+    pub utf8_text: Vec<ITXtChunk>,
+    /// Ancillary chunks not known to the decoder, in the order they were encountered
+    ///
+    /// Only collected if enabled with `Decoder::set_preserve_unknown_chunks`.
+    pub unknown_chunks: Vec<UnknownChunk>,
 }
 
 impl Default for Info {
@@ -398,7 +987,27 @@ impl Default for Info {
             // to maintain backward compatible output.
             compression: Compression::Fast,
             filter: filter::FilterType::Sub,
+            adaptive_filter: filter::AdaptiveFilterType::NonAdaptive,
             source_chromaticities: None,
+            icc_profile: None,
+            srgb: None,
+            significant_bits: None,
+            background_color: None,
+            histogram: None,
+            suggested_palettes: Vec::new(),
+            last_modified: None,
+            exif_metadata: None,
+            coding_independent_code_points: None,
+            mastering_display_color_volume: None,
+            content_light_level: None,
+            offset: None,
+            pixel_calibration: None,
+            physical_scale: None,
+            stereo_layout: None,
+            uncompressed_latin1_text: Vec::new(),
+            compressed_latin1_text: Vec::new(),
+            utf8_text: Vec::new(),
+            unknown_chunks: Vec::new(),
         }
     }
 }
@@ -424,6 +1033,13 @@ impl Info {
         self.frame_control.as_ref()
     }
 
+    /// Returns the orientation tag of the Exif metadata, if there is one.
+    pub fn orientation(&self) -> Option<Orientation> {
+        self.exif_metadata
+            .as_ref()
+            .and_then(|exif| Orientation::from_exif(exif))
+    }
+
     /// Returns the bits per pixel
     pub fn bits_per_pixel(&self) -> usize {
         self.color_type.samples() * self.bit_depth as usize
@@ -455,6 +1071,34 @@ impl Info {
         }
     }
 
+    /// Checks that `bits` has one entry per channel of the color type, each between 1 and the
+    /// sample depth, as required for the sBIT chunk.
+    pub(crate) fn significant_bits_valid(&self, bits: &[u8]) -> bool {
+        let (channels, depth) = match self.color_type {
+            ColorType::Indexed => (3, 8),
+            color_type => (color_type.samples(), self.bit_depth as u8),
+        };
+        bits.len() == channels && bits.iter().all(|&b| b > 0 && b <= depth)
+    }
+
+    /// Checks that `color` matches the color type, refers to an entry of the palette or has
+    /// samples within the bit depth, as required for the bKGD chunk.
+    pub(crate) fn background_color_valid(&self, color: BackgroundColor) -> bool {
+        let max = (1u32 << (self.bit_depth as u8)) - 1;
+        let fits = |sample: u16| u32::from(sample) <= max;
+        match (color, self.color_type) {
+            (BackgroundColor::Indexed(index), ColorType::Indexed) => {
+                let entries = self.palette.as_ref().map_or(0, |palette| palette.len() / 3);
+                usize::from(index) < entries
+            }
+            (BackgroundColor::Gray(gray), ColorType::Grayscale)
+            | (BackgroundColor::Gray(gray), ColorType::GrayscaleAlpha) => fits(gray),
+            (BackgroundColor::Rgb(r, g, b), ColorType::RGB)
+            | (BackgroundColor::Rgb(r, g, b), ColorType::RGBA) => fits(r) && fits(g) && fits(b),
+            _ => false,
+        }
+    }
+
     /// Returns the number of bytes needed for one deinterlaced image
     pub fn raw_bytes(&self) -> usize {
         self.height as usize * self.raw_row_length()
@@ -486,15 +1130,19 @@ impl BytesPerPixel {
 bitflags! {
     /// # Output transformations
     ///
-    /// Only `IDENTITY` and `TRANSFORM_EXPAND | TRANSFORM_STRIP_ALPHA` can be used at the moment.
+    /// The `Reader` applies all read transformations, write only transformations are ignored.
+    ///
+    /// `BGR`, `SWAP_ALPHA`, `INVERT_ALPHA` and `SWAP_ENDIAN` only reorder or invert samples, the
+    /// color type and bit depth reported for the output are those of the channels before the
+    /// reordering.
     pub struct Transformations: u32 {
         /// No transformation
         const IDENTITY            = 0x0000; // read and write */
-        /// Strip 16-bit samples to 8 bits
+        /// Strip 16-bit samples to 8 bits by keeping their high byte, unless `SCALE_16` is set
         const STRIP_16            = 0x0001; // read only */
         /// Discard the alpha channel
         const STRIP_ALPHA         = 0x0002; // read only */
-        /// Expand 1; 2 and 4-bit samples to bytes
+        /// Expand 1; 2 and 4-bit samples to bytes without rescaling their values
         const PACKING             = 0x0004; // read and write */
         /// Change order of packed pixels to LSB first
         const PACKSWAP            = 0x0008; // read and write */
@@ -504,7 +1152,8 @@ bitflags! {
         const EXPAND              = 0x0010; // read only */
         /// Invert monochrome images
         const INVERT_MONO         = 0x0020; // read and write */
-        /// Normalize pixels to the sBIT depth
+        /// Normalize pixels to the sBIT depth by shifting samples right; without effect on
+        /// images without sBIT chunk and on indices
         const SHIFT               = 0x0040; // read and write */
         /// Flip RGB to BGR; RGBA to BGRA
         const BGR                 = 0x0080; // read and write */
@@ -517,9 +1166,20 @@ bitflags! {
         const STRIP_FILLER        = 0x0800; // write only */
         const STRIP_FILLER_BEFORE = 0x0800; // write only
         const STRIP_FILLER_AFTER  = 0x1000; // write only */
+        /// Expand grayscale images to RGB; grayscale with alpha to RGBA
         const GRAY_TO_RGB         = 0x2000; // read only */
+        /// Like `EXPAND` and additionally widen 8-bit samples to 16 bits
         const EXPAND_16           = 0x4000; // read only */
+        /// Scale 16-bit samples to 8 bits, rounding to the nearest value. Takes precedence over
+        /// `STRIP_16` if both are set, as in the default transformations of the decoder.
         const SCALE_16            = 0x8000; // read only */
+        /// Composite pixels onto the background color of the bKGD chunk, or the one set with
+        /// `Decoder::set_background_color`, and discard the alpha channel; black if there is
+        /// neither. Expands images with tRNS chunk like `EXPAND`.
+        const BACKGROUND          = 0x10000; // read only */
+        /// Rotate and flip frames output by `Reader::next_frame` to the Exif orientation of the
+        /// image, swapping width and height for rotations by 90°. Rows are output as stored.
+        const ORIENTATION         = 0x20000; // read only */
     }
 }
 
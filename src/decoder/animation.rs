@@ -0,0 +1,439 @@
+//! Compositing of APNG subframes into complete frames.
+use std::io::Read;
+use std::ops::Range;
+use std::time::Duration;
+
+use super::{DecodingError, Reader};
+use crate::common::{BitDepth, BlendOp, ColorType, DisposeOp, FrameControl, Info, Transformations};
+
+/// The display duration of a composited frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameDelay {
+    /// Frame delay fraction numerator
+    pub numerator: u16,
+    /// Frame delay fraction denominator, a value of 0 is to be treated as 100
+    pub denominator: u16,
+}
+
+impl FrameDelay {
+    /// The delay as a duration, with the denominator defaulting to 100 per specification.
+    pub fn to_duration(&self) -> Duration {
+        let denominator = if self.denominator == 0 {
+            100
+        } else {
+            self.denominator
+        };
+        Duration::from_secs(u64::from(self.numerator)) / u32::from(denominator)
+    }
+}
+
+/// Decodes an animated png (APNG) into complete, ready to display frames.
+///
+/// Each subframe is placed onto a canvas of the size of the image according to its offsets and
+/// blended over the previous frame as requested by its blend operation. The dispose operation of
+/// a frame is applied before the next frame is rendered. Frames are output as 8-bit RGBA, this
+/// requires the `Reader` to produce 8-bit samples which is the case for the default
+/// transformations.
+///
+/// A default image that is not part of the animation is skipped. Images without animation control
+/// produce a single frame.
+///
+/// ```
+/// use std::fs::File;
+/// use png::{AnimationDecoder, Decoder};
+///
+/// let decoder = Decoder::new(File::open("tests/animated/basic_f20.png").unwrap());
+/// let (_, reader) = decoder.read_info().unwrap();
+/// let mut animation = AnimationDecoder::new(reader).unwrap();
+/// let mut canvas = vec![0; animation.output_buffer_size()];
+/// while let Some(delay) = animation.next_frame(&mut canvas).unwrap() {
+///     // Display the RGBA `canvas` for `delay.to_duration()`.
+/// }
+/// ```
+pub struct AnimationDecoder<R: Read> {
+    reader: Reader<R>,
+    /// The fully composited current frame.
+    canvas: Vec<u8>,
+    /// A copy of the canvas before the last frame was rendered, for `DisposeOp::Previous`.
+    previous: Vec<u8>,
+    /// The raw subframe as decoded by the reader.
+    subframe: Vec<u8>,
+    /// The frame control of the last rendered frame, its dispose operation is still pending.
+    last: Option<FrameControl>,
+    /// Number of frames yet to be rendered.
+    remaining: u32,
+}
+
+impl<R: Read> AnimationDecoder<R> {
+    /// Create a compositor for the frames of the reader.
+    ///
+    /// The reader must not have decoded any frame yet. Fails if the reader does not output 8-bit
+    /// samples or reorients frames with `Transformations::ORIENTATION`.
+    pub fn new(reader: Reader<R>) -> Result<Self, DecodingError> {
+        if reader.transform.contains(Transformations::ORIENTATION) {
+            return Err(DecodingError::Other(
+                "compositing requires frames in their stored orientation".into(),
+            ));
+        }
+        match reader.imm_output_color_type() {
+            (ColorType::Indexed, _) | (_, BitDepth::One) | (_, BitDepth::Two) => {
+                return Err(DecodingError::Other(
+                    "compositing requires expanded output samples".into(),
+                ))
+            }
+            (_, BitDepth::Four) | (_, BitDepth::Sixteen) => {
+                return Err(DecodingError::Other(
+                    "compositing requires 8-bit output samples".into(),
+                ))
+            }
+            _ => {}
+        }
+
+        let info = reader.info();
+        let remaining = match info.animation_control() {
+            Some(actl) => actl.num_frames,
+            None => 1,
+        };
+        let canvas_size = (info.width as usize)
+            .checked_mul(info.height as usize)
+            .and_then(|pixels| pixels.checked_mul(4))
+            .ok_or(DecodingError::LimitsExceeded)?;
+        let subframe_size = reader.output_buffer_size();
+
+        Ok(AnimationDecoder {
+            reader,
+            canvas: vec![0; canvas_size],
+            previous: vec![0; canvas_size],
+            subframe: vec![0; subframe_size],
+            last: None,
+            remaining,
+        })
+    }
+
+    /// Get information on the image.
+    pub fn info(&self) -> &Info {
+        self.reader.info()
+    }
+
+    /// Returns the number of bytes required to hold a composited RGBA frame.
+    pub fn output_buffer_size(&self) -> usize {
+        self.canvas.len()
+    }
+
+    /// Renders the next frame of the animation into `buf`.
+    ///
+    /// Returns the time the frame is to be displayed, or `None` after the last frame.
+    pub fn next_frame(&mut self, buf: &mut [u8]) -> Result<Option<FrameDelay>, DecodingError> {
+        if buf.len() < self.canvas.len() {
+            return Err(DecodingError::Other(
+                "supplied buffer is too small to hold the image".into(),
+            ));
+        }
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+
+        self.reader.next_frame(&mut self.subframe)?;
+        let is_animated = self.reader.info().animation_control.is_some();
+        let control = match self.reader.info().frame_control {
+            // The default image is not part of the animation.
+            None if is_animated => self.reader.next_frame(&mut self.subframe).map(|_| {
+                self.reader
+                    .info()
+                    .frame_control
+                    .expect("Subframes after the default image have a frame control")
+            })?,
+            None => FrameControl {
+                width: self.reader.info().width,
+                height: self.reader.info().height,
+                delay_num: 0,
+                delay_den: 1,
+                ..FrameControl::default()
+            },
+            Some(control) => control,
+        };
+        self.remaining -= 1;
+
+        if let Some(last) = self.last.take() {
+            self.dispose(&last);
+        }
+        if control.dispose_op == DisposeOp::Previous {
+            self.previous.copy_from_slice(&self.canvas);
+        }
+        self.blend(&control);
+        self.last = Some(control);
+
+        buf[..self.canvas.len()].copy_from_slice(&self.canvas);
+        Ok(Some(FrameDelay {
+            numerator: control.delay_num,
+            denominator: control.delay_den,
+        }))
+    }
+
+    /// Apply the dispose operation of a frame after it has been displayed.
+    fn dispose(&mut self, control: &FrameControl) {
+        let width = self.reader.info().width;
+        match control.dispose_op {
+            DisposeOp::None => {}
+            DisposeOp::Background => {
+                for row in canvas_rows(width, control) {
+                    self.canvas[row].iter_mut().for_each(|sample| *sample = 0);
+                }
+            }
+            DisposeOp::Previous => {
+                for row in canvas_rows(width, control) {
+                    self.canvas[row.clone()].copy_from_slice(&self.previous[row]);
+                }
+            }
+        }
+    }
+
+    /// Place the decoded subframe onto the canvas.
+    fn blend(&mut self, control: &FrameControl) {
+        let (color_type, _) = self.reader.imm_output_color_type();
+        let samples = color_type.samples();
+        let width = self.reader.info().width;
+        if control.width == 0 {
+            return;
+        }
+        let lines = self.subframe.chunks_exact(control.width as usize * samples);
+        for (line, row) in lines.zip(canvas_rows(width, control)) {
+            let row = &mut self.canvas[row];
+            for (pixel, target) in line.chunks_exact(samples).zip(row.chunks_exact_mut(4)) {
+                let source = to_rgba(color_type, pixel);
+                match control.blend_op {
+                    BlendOp::Source => target.copy_from_slice(&source),
+                    BlendOp::Over => blend_over(source, target),
+                }
+            }
+        }
+    }
+}
+
+/// The byte ranges of the canvas rows covered by a subframe.
+fn canvas_rows(width: u32, control: &FrameControl) -> impl Iterator<Item = Range<usize>> {
+    let width = width as usize;
+    let (x, y) = (control.x_offset as usize, control.y_offset as usize);
+    let row_len = control.width as usize * 4;
+    (y..y + control.height as usize).map(move |line| {
+        let start = (line * width + x) * 4;
+        start..start + row_len
+    })
+}
+
+/// Convert a pixel of 8-bit samples to RGBA.
+fn to_rgba(color_type: ColorType, pixel: &[u8]) -> [u8; 4] {
+    match color_type {
+        ColorType::Grayscale => [pixel[0], pixel[0], pixel[0], 0xFF],
+        ColorType::GrayscaleAlpha => [pixel[0], pixel[0], pixel[0], pixel[1]],
+        ColorType::RGB => [pixel[0], pixel[1], pixel[2], 0xFF],
+        ColorType::RGBA => [pixel[0], pixel[1], pixel[2], pixel[3]],
+        ColorType::Indexed => unreachable!("Indexed output is rejected when creating the decoder"),
+    }
+}
+
+/// Composite a non-premultiplied RGBA pixel over another.
+fn blend_over(source: [u8; 4], target: &mut [u8]) {
+    let source_alpha = u32::from(source[3]);
+    match source_alpha {
+        0 => {}
+        0xFF => target.copy_from_slice(&source),
+        _ => {
+            // Scaled by 255 to keep the intermediate values exact.
+            let target_alpha = u32::from(target[3]) * (0xFF - source_alpha);
+            let alpha = source_alpha * 0xFF + target_alpha;
+            for (t, &s) in target[..3].iter_mut().zip(&source[..3]) {
+                let value = u32::from(s) * source_alpha * 0xFF + u32::from(*t) * target_alpha;
+                *t = ((value + alpha / 2) / alpha) as u8;
+            }
+            target[3] = ((alpha + 0x7F) / 0xFF) as u8;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::blend_over;
+
+    #[test]
+    fn blend_over_extremes() {
+        let mut target = [10, 20, 30, 40];
+        blend_over([1, 2, 3, 0], &mut target);
+        assert_eq!(target, [10, 20, 30, 40]);
+        blend_over([1, 2, 3, 255], &mut target);
+        assert_eq!(target, [1, 2, 3, 255]);
+    }
+
+    #[test]
+    fn blend_over_partial() {
+        let mut target = [0, 0, 255, 255];
+        blend_over([255, 0, 0, 128], &mut target);
+        assert_eq!(target, [128, 0, 127, 255]);
+
+        let mut target = [0, 0, 0, 0];
+        blend_over([255, 0, 0, 128], &mut target);
+        assert_eq!(target, [255, 0, 0, 128]);
+    }
+
+    /// Composites small animations written by the encoder.
+    #[cfg(feature = "png-encoding")]
+    mod compositing {
+        use crate::{AnimationDecoder, BitDepth, BlendOp, ColorType, Decoder, DisposeOp, Encoder};
+
+        const RED: [u8; 4] = [0xFF, 0, 0, 0xFF];
+        const GREEN: [u8; 4] = [0, 0xFF, 0, 0xFF];
+        const BLUE: [u8; 4] = [0, 0, 0xFF, 0xFF];
+        const CLEAR: [u8; 4] = [0, 0, 0, 0];
+
+        /// A subframe of an animation of 4x2 RGBA pixels.
+        struct Frame {
+            position: (u32, u32),
+            size: (u32, u32),
+            dispose_op: DisposeOp,
+            blend_op: BlendOp,
+            pixels: Vec<[u8; 4]>,
+        }
+
+        impl Frame {
+            fn new(position: (u32, u32), size: (u32, u32), pixels: Vec<[u8; 4]>) -> Self {
+                Frame {
+                    position,
+                    size,
+                    dispose_op: DisposeOp::None,
+                    blend_op: BlendOp::Source,
+                    pixels,
+                }
+            }
+
+            fn filled(position: (u32, u32), size: (u32, u32), color: [u8; 4]) -> Self {
+                Frame::new(position, size, vec![color; (size.0 * size.1) as usize])
+            }
+        }
+
+        /// Encodes the frames, of which the first covers the whole image, and composites them.
+        fn composite(frames: &[Frame]) -> Vec<Vec<[u8; 4]>> {
+            let mut png = Vec::new();
+            {
+                let mut encoder = Encoder::new(&mut png, 4, 2);
+                encoder.set_color(ColorType::RGBA);
+                encoder.set_depth(BitDepth::Eight);
+                encoder.set_animated(frames.len() as u32, 0).unwrap();
+                encoder.set_dispose_op(frames[0].dispose_op).unwrap();
+                encoder.set_blend_op(frames[0].blend_op).unwrap();
+                let mut writer = encoder.write_header().unwrap();
+                writer.write_image_data(&frames[0].pixels.concat()).unwrap();
+                for frame in &frames[1..] {
+                    writer
+                        .set_frame_dimension(frame.size.0, frame.size.1)
+                        .unwrap();
+                    writer
+                        .set_frame_position(frame.position.0, frame.position.1)
+                        .unwrap();
+                    writer.set_dispose_op(frame.dispose_op).unwrap();
+                    writer.set_blend_op(frame.blend_op).unwrap();
+                    writer.write_image_data(&frame.pixels.concat()).unwrap();
+                }
+            }
+
+            let (_, reader) = Decoder::new(&png[..]).read_info().unwrap();
+            let mut animation = AnimationDecoder::new(reader).unwrap();
+            let mut canvas = vec![0; animation.output_buffer_size()];
+            let mut canvases = vec![];
+            while animation.next_frame(&mut canvas).unwrap().is_some() {
+                let pixels = canvas.chunks(4).map(|p| [p[0], p[1], p[2], p[3]]);
+                canvases.push(pixels.collect());
+            }
+            canvases
+        }
+
+        #[test]
+        fn offset_frame() {
+            let frames = [
+                Frame::filled((0, 0), (4, 2), RED),
+                Frame::filled((1, 1), (2, 1), GREEN),
+            ];
+            let canvases = composite(&frames);
+            assert_eq!(canvases[0], vec![RED; 8]);
+            assert_eq!(
+                canvases[1],
+                vec![RED, RED, RED, RED, RED, GREEN, GREEN, RED]
+            );
+        }
+
+        #[test]
+        fn dispose_background() {
+            let mut frames = [
+                Frame::filled((0, 0), (4, 2), RED),
+                Frame::filled((2, 0), (2, 1), GREEN),
+                Frame::filled((0, 1), (1, 1), BLUE),
+            ];
+            frames[1].dispose_op = DisposeOp::Background;
+            let canvases = composite(&frames);
+            assert_eq!(
+                canvases[1],
+                vec![RED, RED, GREEN, GREEN, RED, RED, RED, RED]
+            );
+            assert_eq!(
+                canvases[2],
+                vec![RED, RED, CLEAR, CLEAR, BLUE, RED, RED, RED]
+            );
+        }
+
+        #[test]
+        fn dispose_previous() {
+            let mut frames = [
+                Frame::filled((0, 0), (4, 2), RED),
+                Frame::filled((0, 0), (2, 2), GREEN),
+                Frame::filled((3, 1), (1, 1), BLUE),
+            ];
+            frames[1].dispose_op = DisposeOp::Previous;
+            let canvases = composite(&frames);
+            assert_eq!(
+                canvases[1],
+                vec![GREEN, GREEN, RED, RED, GREEN, GREEN, RED, RED]
+            );
+            assert_eq!(canvases[2], vec![RED, RED, RED, RED, RED, RED, RED, BLUE]);
+
+            // On the first frame it clears the canvas like `DisposeOp::Background`.
+            let mut frames = [
+                Frame::filled((0, 0), (4, 2), RED),
+                Frame::filled((0, 0), (1, 1), GREEN),
+            ];
+            frames[0].dispose_op = DisposeOp::Previous;
+            let canvases = composite(&frames);
+            let mut expected = vec![CLEAR; 8];
+            expected[0] = GREEN;
+            assert_eq!(canvases[1], expected);
+        }
+
+        #[test]
+        fn blend_over_frame() {
+            let half_blue = [0, 0, 0xFF, 0x80];
+            let mut frames = [
+                Frame::filled((0, 0), (4, 2), RED),
+                Frame::new(
+                    (0, 0),
+                    (4, 2),
+                    vec![
+                        half_blue, CLEAR, half_blue, CLEAR, half_blue, CLEAR, half_blue, CLEAR,
+                    ],
+                ),
+            ];
+            frames[1].blend_op = BlendOp::Over;
+            let canvases = composite(&frames);
+            let blended = [0x7F, 0, 0x80, 0xFF];
+            assert_eq!(
+                canvases[1],
+                vec![blended, RED, blended, RED, blended, RED, blended, RED]
+            );
+
+            // With `BlendOp::Source` the pixels replace the canvas.
+            frames[1].blend_op = BlendOp::Source;
+            let canvases = composite(&frames);
+            assert_eq!(
+                canvases[1],
+                vec![half_blue, CLEAR, half_blue, CLEAR, half_blue, CLEAR, half_blue, CLEAR]
+            );
+        }
+    }
+}
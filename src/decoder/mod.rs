@@ -1,6 +1,9 @@
+mod animation;
 mod stream;
+mod transform;
 mod zlib;
 
+pub use self::animation::{AnimationDecoder, FrameDelay};
 use self::stream::{get_info, CHUNCK_BUFFER_SIZE};
 pub use self::stream::{Decoded, DecodingError, StreamingDecoder};
 
@@ -10,20 +13,21 @@ use std::mem;
 use std::ops::Range;
 
 use crate::chunk;
-use crate::common::{BitDepth, BytesPerPixel, ColorType, Info, Transformations};
+use crate::common::{BitDepth, BytesPerPixel, ColorType, Info, Orientation, Transformations};
 use crate::filter::{unfilter, FilterType};
 use crate::utils;
 
-/*
+/// How `Reader::next_pass` outputs the passes of interlaced images
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum InterlaceHandling {
-    /// Outputs the raw rows
+    /// Outputs the raw rows of the pass, packed like a frame of the reduced size of the pass
     RawRows,
-    /// Fill missing the pixels from the existing ones
+    /// Fills the pixels that are still missing from the existing ones, each pixel of a pass
+    /// covering the block up to the next pixel of the pass
     Rectangle,
-    /// Only fill the needed pixels
-    Sparkle
+    /// Only fills the pixels of the pass, leaving all others unchanged
+    Sparkle,
 }
-*/
 
 /// Output info
 #[derive(Debug, PartialEq, Eq)]
@@ -65,6 +69,14 @@ pub struct Decoder<R: Read> {
     transform: Transformations,
     /// Limits on resources the Decoder is allowed to use
     limits: Limits,
+    /// The gamma of the screen to correct the output for
+    screen_gamma: Option<f64>,
+    /// The color to composite onto instead of the bKGD color
+    background_color: Option<(u16, u16, u16)>,
+    /// Whether to collect unknown ancillary chunks
+    preserve_unknown_chunks: bool,
+    /// How `Reader::next_pass` outputs passes
+    interlace_handling: InterlaceHandling,
 }
 
 struct InterlacedRow<'data> {
@@ -89,6 +101,10 @@ impl<R: Read> Decoder<R> {
                 | crate::Transformations::SCALE_16
                 | crate::Transformations::STRIP_16,
             limits,
+            screen_gamma: None,
+            background_color: None,
+            preserve_unknown_chunks: false,
+            interlace_handling: InterlaceHandling::Rectangle,
         }
     }
 
@@ -114,7 +130,13 @@ impl<R: Read> Decoder<R> {
 
     /// Reads all meta data until the first IDAT chunk
     pub fn read_info(self) -> Result<(OutputInfo, Reader<R>), DecodingError> {
-        let mut r = Reader::new(self.r, StreamingDecoder::new(), self.transform, self.limits);
+        let mut decoder = StreamingDecoder::new();
+        decoder.set_limits(self.limits);
+        decoder.set_preserve_unknown_chunks(self.preserve_unknown_chunks);
+        let mut r = Reader::new(self.r, decoder, self.transform, self.limits);
+        r.screen_gamma = self.screen_gamma;
+        r.background_color = self.background_color;
+        r.interlace_handling = self.interlace_handling;
         r.init()?;
 
         let color_type = r.info().color_type;
@@ -135,15 +157,13 @@ impl<R: Read> Decoder<R> {
         }
 
         let (ct, bits) = r.output_color_type();
-        let info = {
-            let info = r.info();
-            OutputInfo {
-                width: info.width,
-                height: info.height,
-                color_type: ct,
-                bit_depth: bits,
-                line_size: r.output_line_size(info.width),
-            }
+        let (width, height) = r.output_size();
+        let info = OutputInfo {
+            width,
+            height,
+            color_type: ct,
+            bit_depth: bits,
+            line_size: r.output_line_size(width),
         };
         Ok((info, r))
     }
@@ -155,6 +175,38 @@ impl<R: Read> Decoder<R> {
     pub fn set_transformations(&mut self, transform: Transformations) {
         self.transform = transform;
     }
+
+    /// Correct the output samples for display on a screen with the gamma `screen_gamma`.
+    ///
+    /// Samples are converted from the gamma of the gAMA chunk, or 1/2.2 if the image has none, to
+    /// the screen gamma, typically 2.2. Alpha samples are left unchanged and the palette of
+    /// indexed images is corrected when expanding them with `Transformations::EXPAND`. Indices
+    /// and samples of less than 8 bits that are not expanded are output uncorrected.
+    pub fn set_gamma_correction(&mut self, screen_gamma: f64) {
+        self.screen_gamma = Some(screen_gamma);
+    }
+
+    /// Composite onto `rgb` instead of the bKGD color with `Transformations::BACKGROUND`.
+    ///
+    /// The samples are 16 bits and scaled to the bit depth of the image, grayscale images are
+    /// composited onto the luminance of the color.
+    pub fn set_background_color(&mut self, rgb: (u16, u16, u16)) {
+        self.background_color = Some(rgb);
+    }
+
+    /// Collect the ancillary chunks not known to the decoder into `Info::unknown_chunks`.
+    ///
+    /// Each chunk records whether it appeared before PLTE, before or after the image data so
+    /// that `Encoder::add_unknown_chunk` can write it back to the same position. Their data
+    /// counts towards the limits of the decoder.
+    pub fn set_preserve_unknown_chunks(&mut self, preserve: bool) {
+        self.preserve_unknown_chunks = preserve;
+    }
+
+    /// Set how `Reader::next_pass` fills the output buffer, the default is `Rectangle`.
+    pub fn set_interlace_handling(&mut self, handling: InterlaceHandling) {
+        self.interlace_handling = handling;
+    }
 }
 
 struct ReadDecoder<R: Read> {
@@ -235,6 +287,18 @@ pub struct Reader<R: Read> {
     /// Processed line
     processed: Vec<u8>,
     limits: Limits,
+    /// The gamma of the screen to correct the output for
+    screen_gamma: Option<f64>,
+    /// Gamma correction tables, built once the header chunks are read
+    gamma: Option<transform::GammaCorrection>,
+    /// The color to composite onto instead of the bKGD color
+    background_color: Option<(u16, u16, u16)>,
+    /// The background to composite onto, for images with alpha
+    background: Option<transform::Background>,
+    /// How `next_pass` outputs passes
+    interlace_handling: InterlaceHandling,
+    /// How far `next_pass` has decoded the current frame
+    pass_progress: PassProgress,
 }
 
 /// The subframe specific information.
@@ -256,6 +320,15 @@ enum InterlaceIter {
     Adam7(utils::Adam7Iterator),
 }
 
+/// The progress of decoding a frame pass by pass.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum PassProgress {
+    NotStarted,
+    Decoding,
+    /// All passes have been decoded, which is yet to be reported.
+    Finished,
+}
+
 /// Denote a frame as given by sequence numbers.
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 enum SubframeIdx {
@@ -271,6 +344,15 @@ enum SubframeIdx {
     End,
 }
 
+/// Clamps a pass number to the Adam7 passes.
+fn adam7_pass(pass: u8) -> u8 {
+    match pass {
+        0 => 1,
+        1..=7 => pass,
+        _ => 7,
+    }
+}
+
 macro_rules! get_info(
     ($this:expr) => {
         $this.decoder.info().unwrap()
@@ -296,6 +378,12 @@ impl<R: Read> Reader<R> {
             transform: t,
             processed: Vec::new(),
             limits,
+            screen_gamma: None,
+            gamma: None,
+            background_color: None,
+            background: None,
+            interlace_handling: InterlaceHandling::Rectangle,
+            pass_progress: PassProgress::NotStarted,
         }
     }
 
@@ -340,6 +428,17 @@ impl<R: Read> Reader<R> {
             // Now we can init the subframe info.
             // TODO: reuse the results obtained during the above check.
             self.subframe = SubframeInfo::new(info);
+            if let (Some(screen_gamma), None) = (self.screen_gamma, &self.gamma) {
+                self.gamma = Some(transform::GammaCorrection::new(info, screen_gamma));
+            }
+            if self.transform.contains(Transformations::BACKGROUND) {
+                self.background = transform::Background::new(
+                    info,
+                    self.transform,
+                    self.background_color,
+                    self.gamma.as_ref(),
+                );
+            }
         }
         self.allocate_out_buf()?;
         self.prev = vec![0; self.subframe.rowlen];
@@ -400,7 +499,8 @@ impl<R: Read> Reader<R> {
     /// Decodes the next frame into `buf`.
     ///
     /// Note that this decodes raw subframes that need to be mixed according to blend-op and
-    /// dispose-op by the caller.
+    /// dispose-op by the caller. An `AnimationDecoder` can be used to obtain complete frames
+    /// instead.
     ///
     /// The caller must always provide a buffer large enough to hold a complete frame (the APNG
     /// specification restricts subframes to the dimensions given in the image header). The region
@@ -410,29 +510,128 @@ impl<R: Read> Reader<R> {
     ///
     /// Output lines will be written in row-major, packed matrix with width and height of the read
     /// frame (or subframe), all samples are in big endian byte order where this matters.
+    ///
+    /// With `Transformations::ORIENTATION` the frame is rotated and flipped for display according
+    /// to the Exif orientation, which can swap its width and height.
     pub fn next_frame(&mut self, buf: &mut [u8]) -> Result<(), DecodingError> {
         // Advance until we've read the info / fcTL for this frame.
         self.init()?;
-        // TODO 16 bit
-        let (color_type, bit_depth) = self.output_color_type();
         if buf.len() < self.output_buffer_size() {
             return Err(DecodingError::Other(
                 "supplied buffer is too small to hold the image".into(),
             ));
         }
 
+        let stride = self.output_line_size(self.output_frame_size().0);
+        self.decode_frame(buf, stride)
+    }
+
+    /// Decodes the next frame into `buf` with its rows `stride` bytes apart, such as a buffer
+    /// with padding after each row.
+    ///
+    /// The stride must be at least `output_line_size` of the frame width and the buffer must hold
+    /// all rows, the last one without padding. The bytes between the rows are left unchanged.
+    /// Apart from that this is the same as `next_frame`.
+    pub fn next_frame_strided(
+        &mut self,
+        buf: &mut [u8],
+        stride: usize,
+    ) -> Result<(), DecodingError> {
+        self.init()?;
+        let (width, height) = self.output_frame_size();
+        self.check_strided_buffer(buf, stride, (width, height))?;
+        self.decode_frame(buf, stride)
+    }
+
+    /// Decodes the next frame into `canvas`, a buffer of the size of the image with its rows
+    /// `stride` bytes apart, placing subframes of an animated image at their offset.
+    ///
+    /// Only the pixels of the subframe are written, its dispose and blend operations are left to
+    /// the caller. The offset must lie on a byte boundary of the output, which excludes some
+    /// offsets for less than 8 bits per pixel. Reoriented frames are not supported.
+    pub fn next_frame_on_canvas(
+        &mut self,
+        canvas: &mut [u8],
+        stride: usize,
+    ) -> Result<(), DecodingError> {
+        self.init()?;
+        if self.orientation() != Orientation::TopLeft {
+            return Err(DecodingError::Other(
+                "reoriented frames can not be placed on a canvas".into(),
+            ));
+        }
+        let info = get_info!(self);
+        let size = info.size();
+        let (x_offset, y_offset) = info
+            .frame_control
+            .map_or((0, 0), |fc| (fc.x_offset, fc.y_offset));
+        self.check_strided_buffer(canvas, stride, size)?;
+        let (color_type, bit_depth) = self.output_color_type();
+        let offset_bits = x_offset as usize * color_type.samples() * bit_depth as usize;
+        if offset_bits & 7 != 0 {
+            return Err(DecodingError::Other(
+                "subframe offset is not on a byte boundary".into(),
+            ));
+        }
+        let start = y_offset as usize * stride + offset_bits / 8;
+        self.decode_frame(&mut canvas[start..], stride)
+    }
+
+    /// Fail unless `buf` holds a frame of `size` with rows `stride` bytes apart.
+    fn check_strided_buffer(
+        &self,
+        buf: &[u8],
+        stride: usize,
+        (width, height): (u32, u32),
+    ) -> Result<(), DecodingError> {
+        let line_size = self.output_line_size(width);
+        if stride < line_size {
+            return Err(DecodingError::Other(
+                "stride is smaller than a row of the image".into(),
+            ));
+        }
+        let len = stride
+            .checked_mul(height.saturating_sub(1) as usize)
+            .and_then(|len| len.checked_add(line_size));
+        match len {
+            Some(len) if len <= buf.len() => Ok(()),
+            _ => Err(DecodingError::Other(
+                "supplied buffer is too small to hold the image".into(),
+            )),
+        }
+    }
+
+    /// Decodes the frame into `buf` with its rows `stride` bytes apart and advances to the next.
+    fn decode_frame(&mut self, buf: &mut [u8], stride: usize) -> Result<(), DecodingError> {
         self.reset_current();
-        let width = self.info().width;
-        if get_info!(self).interlaced {
-            while let Some((row, adam7)) = self.next_interlaced_row()? {
-                let (pass, line, _) = adam7.unwrap();
-                let samples = color_type.samples() as u8;
-                utils::expand_pass(buf, width, row, pass, line, samples * (bit_depth as u8));
-            }
+        let (color_type, bit_depth) = self.output_color_type();
+        let bits_pp = color_type.samples() * bit_depth as usize;
+        // Reoriented frames are decoded as stored first.
+        let orientation = self.orientation();
+        let frame_size = self.frame_size();
+        if orientation == Orientation::TopLeft {
+            self.decode_rows(buf, stride, frame_size.0, bits_pp)?;
         } else {
-            let mut len = 0;
-            while let Some(row) = self.next_row()? {
-                len += (&mut buf[len..]).write(row)?;
+            let line_size = self.output_line_size(frame_size.0);
+            let mut stored = vec![0; line_size * frame_size.1 as usize];
+            self.decode_rows(&mut stored, line_size, frame_size.0, bits_pp)?;
+            let out_line_size = self.output_line_size(self.output_frame_size().0);
+            let lsb_first = self.transform.contains(Transformations::PACKSWAP);
+            if stride == out_line_size {
+                transform::orient_frame(&stored, buf, frame_size, bits_pp, lsb_first, orientation);
+            } else {
+                let mut oriented = vec![0; out_line_size * self.output_frame_size().1 as usize];
+                transform::orient_frame(
+                    &stored,
+                    &mut oriented,
+                    frame_size,
+                    bits_pp,
+                    lsb_first,
+                    orientation,
+                );
+                for (row, out) in oriented.chunks(out_line_size).zip(buf.chunks_mut(stride)) {
+                    out[..out_line_size].copy_from_slice(row);
+                }
             }
         }
         // Advance over the rest of data for this (sub-)frame.
@@ -444,6 +643,323 @@ impl<R: Read> Reader<R> {
         Ok(())
     }
 
+    /// Writes the rows of the frame, which is `width` pixels wide, `stride` bytes apart.
+    fn decode_rows(
+        &mut self,
+        buf: &mut [u8],
+        stride: usize,
+        width: u32,
+        bits_pp: usize,
+    ) -> Result<(), DecodingError> {
+        let lsb_first = self.transform.contains(Transformations::PACKSWAP);
+        if get_info!(self).interlaced {
+            while let Some((row, adam7)) = self.next_interlaced_row()? {
+                let (pass, line, _) = adam7.unwrap();
+                let start = utils::adam7_line(pass, line) * stride;
+                let bits_pp = bits_pp as u8;
+                utils::expand_pass_row(&mut buf[start..], width, row, pass, bits_pp, lsb_first);
+            }
+        } else {
+            // Pixels following a row of less than 8 bits per pixel in its last byte are kept.
+            let row_bits = width as usize * bits_pp;
+            let mut start = 0;
+            while let Some(row) = self.next_row()? {
+                utils::copy_bits(row, row_bits, &mut buf[start..], lsb_first);
+                start += stride;
+            }
+        }
+        Ok(())
+    }
+
+    /// Decodes the next Adam7 pass of the current frame into `buf` and returns its number.
+    ///
+    /// The buffer must be large enough to hold a complete frame. It is filled according to the
+    /// `InterlaceHandling` set with `Decoder::set_interlace_handling`, so that a viewer can
+    /// display it after each pass. Passes without pixels, which occur in small images, are
+    /// skipped. Images that are not interlaced are decoded in a single pass numbered 7, the last
+    /// Adam7 pass.
+    ///
+    /// Returns `None` once all passes of the frame have been decoded, the next call continues with
+    /// the next frame of an animated image. Frames that `Transformations::ORIENTATION` would
+    /// rotate or flip can only be decoded with `next_frame`, and the two methods must not be mixed
+    /// within a frame.
+    pub fn next_pass(&mut self, buf: &mut [u8]) -> Result<Option<u8>, DecodingError> {
+        match self.pass_progress {
+            PassProgress::Finished => {
+                self.pass_progress = PassProgress::NotStarted;
+                return Ok(None);
+            }
+            PassProgress::NotStarted => {
+                self.init()?;
+                if self.orientation() != Orientation::TopLeft {
+                    return Err(DecodingError::Other(
+                        "reoriented frames can not be decoded pass by pass".into(),
+                    ));
+                }
+                if buf.len() < self.output_buffer_size() {
+                    return Err(DecodingError::Other(
+                        "supplied buffer is too small to hold the image".into(),
+                    ));
+                }
+                self.reset_current();
+                self.pass_progress = PassProgress::Decoding;
+            }
+            PassProgress::Decoding => {
+                if buf.len() < self.output_buffer_size() {
+                    return Err(DecodingError::Other(
+                        "supplied buffer is too small to hold the image".into(),
+                    ));
+                }
+            }
+        }
+
+        let (color_type, bit_depth) = self.output_color_type();
+        let bits_pp = color_type.samples() as u8 * bit_depth as u8;
+        let lsb_first = self.transform.contains(Transformations::PACKSWAP);
+        let frame_size = self.frame_size();
+        let handling = self.interlace_handling;
+        let pass = self.peek_pass();
+        let mut len = 0;
+        while pass.is_some() && self.peek_pass() == pass {
+            let (row, adam7) = match self.next_interlaced_row()? {
+                Some(row) => row,
+                None => break,
+            };
+            match (adam7, handling) {
+                (None, _) => len += (&mut buf[len..]).write(row)?,
+                (Some((_, line, _)), InterlaceHandling::RawRows) => {
+                    let start = line as usize * row.len();
+                    buf[start..start + row.len()].copy_from_slice(row);
+                }
+                (Some((pass, line, _)), InterlaceHandling::Rectangle) => {
+                    utils::expand_pass_rect(buf, frame_size, row, pass, line, bits_pp, lsb_first);
+                }
+                (Some((pass, line, _)), InterlaceHandling::Sparkle) => {
+                    utils::expand_pass(buf, frame_size.0, row, pass, line, bits_pp, lsb_first);
+                }
+            }
+        }
+
+        if self.peek_pass().is_none() {
+            // Advance over the rest of data for this (sub-)frame.
+            if !self.subframe.consumed_and_flushed {
+                self.decoder.finished_decoding()?;
+            }
+            self.finished_frame();
+            self.pass_progress = match pass {
+                Some(_) => PassProgress::Finished,
+                None => PassProgress::NotStarted,
+            };
+        }
+        Ok(pass)
+    }
+
+    /// Decodes the region of `width` × `height` pixels at `x`, `y` of the next frame into `buf`.
+    ///
+    /// The buffer must hold the rows of the region, each `output_line_size(width)` bytes long.
+    /// Of images that are not interlaced, the rows above the region are only unfiltered and the
+    /// transformations apply to the columns of the region alone. Decoding stops after its last
+    /// row and the rest of the image data is skipped, so no further frames can be decoded
+    /// afterwards, but `finish` reads the remaining chunks.
+    ///
+    /// Interlaced and reoriented frames are decoded completely and then cropped, the region then
+    /// refers to the reoriented frame.
+    pub fn next_frame_region(
+        &mut self,
+        buf: &mut [u8],
+        (x, y): (u32, u32),
+        (width, height): (u32, u32),
+    ) -> Result<(), DecodingError> {
+        self.init()?;
+        let orientation = self.orientation();
+        let (frame_width, frame_height) = self.output_frame_size();
+        let fits = |start: u32, len: u32, end: u32| match start.checked_add(len) {
+            Some(region_end) => region_end <= end,
+            None => false,
+        };
+
+        if !fits(x, width, frame_width) || !fits(y, height, frame_height) {
+            return Err(DecodingError::Other("region exceeds the frame".into()));
+        }
+        let line_size = self.output_line_size(width);
+        if buf.len() < line_size * height as usize {
+            return Err(DecodingError::Other(
+                "supplied buffer is too small to hold the region".into(),
+            ));
+        }
+        let (color_type, bit_depth) = self.output_color_type();
+        let bits_pp = color_type.samples() * bit_depth as usize;
+
+        if get_info!(self).interlaced || orientation != Orientation::TopLeft {
+            let frame_line_size = self.output_line_size(frame_width);
+            let mut frame = vec![0; frame_line_size * frame_height as usize];
+            self.decode_frame(&mut frame, frame_line_size)?;
+            let lsb_first = self.transform.contains(Transformations::PACKSWAP);
+            let rows = frame.chunks(frame_line_size).skip(y as usize);
+            for (row, out) in rows.zip(buf.chunks_mut(line_size).take(height as usize)) {
+                utils::crop_line(row, (x as usize, width as usize), bits_pp, lsb_first, out);
+            }
+            return Ok(());
+        }
+
+        self.reset_current();
+        // A separate buffer for the processed rows circumvents borrow issues.
+        let mut processed = vec![0; self.processed.len()];
+        let result = self.decode_region_rows(&mut processed, buf, (x, y), (width, height));
+        self.next_frame = SubframeIdx::End;
+        result
+    }
+
+    /// Unfilters the rows of the frame up to the end of the region and transforms and copies
+    /// the columns of the region.
+    fn decode_region_rows(
+        &mut self,
+        processed: &mut [u8],
+        buf: &mut [u8],
+        (x, y): (u32, u32),
+        (width, height): (u32, u32),
+    ) -> Result<(), DecodingError> {
+        let info = get_info!(self);
+        let raw_bits_pp = info.color_type.samples() * info.bit_depth as usize;
+        let raw_len = info.raw_row_length_from_width(width) - 1;
+        let line_size = self.output_line_size(width);
+        let transform = self.transform;
+        for row_index in 0..y + height {
+            let row = match self.next_raw_interlaced_row()? {
+                Some(row) => row,
+                None => return Err(DecodingError::Format("not enough data for image".into())),
+            };
+            if row_index < y {
+                continue;
+            }
+            let columns = (x as usize, width as usize);
+            utils::crop_line(row.data, columns, raw_bits_pp, false, processed);
+            let len = if transform == crate::Transformations::IDENTITY && self.gamma.is_none() {
+                raw_len
+            } else {
+                transform::transform_row(
+                    processed,
+                    width,
+                    get_info!(self),
+                    transform,
+                    self.gamma.as_ref(),
+                    self.background.as_ref(),
+                )?
+            };
+            let start = (row_index - y) as usize * line_size;
+            buf[start..start + len].copy_from_slice(&processed[..len]);
+        }
+        Ok(())
+    }
+
+    /// Decodes a reduced preview of the next frame from the first passes of an interlaced image
+    /// into `buf` and returns its width and height.
+    ///
+    /// The preview consists of the pixels known after the Adam7 pass `max_pass`, clamped to 1 to
+    /// 7: every 8th pixel in both directions after pass 1, every 4th in both directions after
+    /// pass 3, every 2nd after pass 5 and the complete frame after pass 7, with the even passes
+    /// doubling the horizontal resolution. Decoding stops once the pass is complete, leaving the
+    /// rest of the image data unread. Images that are not interlaced are decoded completely.
+    ///
+    /// The buffer must hold `preview_buffer_size` bytes. No further frames can be decoded
+    /// afterwards, but `finish` reads the remaining chunks. Like `next_pass` this does not support
+    /// reoriented frames.
+    pub fn decode_preview(
+        &mut self,
+        max_pass: u8,
+        buf: &mut [u8],
+    ) -> Result<(u32, u32), DecodingError> {
+        self.init()?;
+        if self.orientation() != Orientation::TopLeft {
+            return Err(DecodingError::Other(
+                "reoriented frames can not be previewed".into(),
+            ));
+        }
+        let max_pass = adam7_pass(max_pass);
+        let size = self.preview_size(max_pass);
+        if !get_info!(self).interlaced {
+            self.next_frame(buf)?;
+            self.next_frame = SubframeIdx::End;
+            return Ok(size);
+        }
+        if buf.len() < self.preview_buffer_size(max_pass) {
+            return Err(DecodingError::Other(
+                "supplied buffer is too small to hold the preview".into(),
+            ));
+        }
+
+        self.reset_current();
+        let (color_type, bit_depth) = self.output_color_type();
+        let bits_pp = color_type.samples() as u8 * bit_depth as u8;
+        let lsb_first = self.transform.contains(Transformations::PACKSWAP);
+        while let Some(pass) = self.peek_pass() {
+            if pass > max_pass {
+                break;
+            }
+            if let Some((row, Some((pass, line, _)))) = self.next_interlaced_row()? {
+                let line = (pass, line);
+                utils::expand_pass_reduced(buf, size.0, row, line, bits_pp, max_pass, lsb_first);
+            } else {
+                break;
+            }
+        }
+        self.next_frame = SubframeIdx::End;
+        Ok(size)
+    }
+
+    /// The width and height of the preview `decode_preview` outputs for `max_pass`.
+    pub fn preview_size(&self, max_pass: u8) -> (u32, u32) {
+        let (width, height) = self.frame_size();
+        if !get_info!(self).interlaced {
+            return (width, height);
+        }
+        let (x_step, y_step) = utils::adam7_block(adam7_pass(max_pass));
+        let reduce = |len: u32, step: usize| (len as usize).saturating_sub(1) / step + 1;
+        (reduce(width, x_step) as u32, reduce(height, y_step) as u32)
+    }
+
+    /// The number of bytes `decode_preview` needs to hold the preview for `max_pass`.
+    pub fn preview_buffer_size(&self, max_pass: u8) -> usize {
+        let (width, height) = self.preview_size(max_pass);
+        self.output_line_size(width) * height as usize
+    }
+
+    /// The width and height of the current frame as output, after reorienting it.
+    fn output_frame_size(&self) -> (u32, u32) {
+        let (width, height) = self.frame_size();
+        if self.orientation().transposes() {
+            (height, width)
+        } else {
+            (width, height)
+        }
+    }
+
+    /// The width and height of the current frame as stored.
+    fn frame_size(&self) -> (u32, u32) {
+        let info = get_info!(self);
+        let height = info.frame_control.map_or(info.height, |fc| fc.height);
+        (self.subframe.width, height)
+    }
+
+    /// The pass of the next row of the frame, 7 for images that are not interlaced.
+    fn peek_pass(&self) -> Option<u8> {
+        match self.subframe.interlace {
+            InterlaceIter::Adam7(ref adam7) => adam7.clone().next().map(|(pass, _, _)| pass),
+            InterlaceIter::None(ref rows) => rows.clone().next().map(|_| 7),
+        }
+    }
+
+    /// Reads all remaining chunks up to the end of the image.
+    ///
+    /// Ancillary chunks that follow the image data, such as text chunks, are only reflected in
+    /// `info` after calling this method. Frames of an animated image that have not been decoded
+    /// yet are skipped, no further frame can be read afterwards.
+    pub fn finish(&mut self) -> Result<(), DecodingError> {
+        self.next_frame = SubframeIdx::End;
+        while self.decoder.decode_next(&mut Vec::new())?.is_some() {}
+        Ok(())
+    }
+
     /// Returns the next processed row of the image
     pub fn next_row(&mut self) -> Result<Option<&[u8]>, DecodingError> {
         self.next_interlaced_row().map(|v| v.map(|v| v.0))
@@ -469,10 +985,9 @@ impl<R: Read> Reader<R> {
 
     /// Fetch the next interlaced row and filter it according to our own transformations.
     fn next_interlaced_row_impl(&mut self) -> Result<Option<InterlacedRow<'_>>, DecodingError> {
-        use crate::common::ColorType::*;
         let transform = self.transform;
 
-        if transform == crate::Transformations::IDENTITY {
+        if transform == crate::Transformations::IDENTITY && self.gamma.is_none() {
             return self.next_raw_interlaced_row();
         }
 
@@ -491,51 +1006,22 @@ impl<R: Read> Reader<R> {
             return Ok(None);
         }
 
-        let (color_type, bit_depth, trns) = {
-            let info = get_info!(self);
-            (info.color_type, info.bit_depth as u8, info.trns.is_some())
-        };
-        let output_buffer = if let InterlaceInfo::Adam7 { width, .. } = adam7 {
-            let width = self
-                .line_size(width)
-                .expect("Adam7 interlaced rows are shorter than the buffer.");
-            &mut self.processed[..width]
+        let width = if let InterlaceInfo::Adam7 { width, .. } = adam7 {
+            width
         } else {
-            &mut *self.processed
+            self.subframe.width
         };
-
-        let mut len = output_buffer.len();
-        if transform.contains(crate::Transformations::EXPAND) {
-            match color_type {
-                Indexed => expand_paletted(output_buffer, get_info!(self))?,
-                Grayscale | GrayscaleAlpha if bit_depth < 8 => {
-                    expand_gray_u8(output_buffer, get_info!(self))
-                }
-                Grayscale | RGB if trns => {
-                    let channels = color_type.samples();
-                    let trns = get_info!(self).trns.as_ref().unwrap();
-                    if bit_depth == 8 {
-                        utils::expand_trns_line(output_buffer, &*trns, channels);
-                    } else {
-                        utils::expand_trns_line16(output_buffer, &*trns, channels);
-                    }
-                }
-                _ => (),
-            }
-        }
-
-        if bit_depth == 16
-            && transform
-                .intersects(crate::Transformations::SCALE_16 | crate::Transformations::STRIP_16)
-        {
-            len /= 2;
-            for i in 0..len {
-                output_buffer[i] = output_buffer[2 * i];
-            }
-        }
+        let len = transform::transform_row(
+            &mut self.processed,
+            width,
+            get_info!(self),
+            transform,
+            self.gamma.as_ref(),
+            self.background.as_ref(),
+        )?;
 
         Ok(Some(InterlacedRow {
-            data: &output_buffer[..len],
+            data: &self.processed[..len],
             interlace: adam7,
         }))
     }
@@ -547,46 +1033,43 @@ impl<R: Read> Reader<R> {
     }
 
     pub(crate) fn imm_output_color_type(&self) -> (ColorType, BitDepth) {
-        use crate::common::ColorType::*;
-        let t = self.transform;
         let info = get_info!(self);
-        if t == crate::Transformations::IDENTITY {
+        if self.transform == crate::Transformations::IDENTITY {
             (info.color_type, info.bit_depth)
         } else {
-            let bits = match info.bit_depth as u8 {
-                16 if t.intersects(
-                    crate::Transformations::SCALE_16 | crate::Transformations::STRIP_16,
-                ) =>
-                {
-                    8
-                }
-                n if n < 8 && t.contains(crate::Transformations::EXPAND) => 8,
-                n => n,
-            };
-            let color_type = if t.contains(crate::Transformations::EXPAND) {
-                let has_trns = info.trns.is_some();
-                match info.color_type {
-                    Grayscale if has_trns => GrayscaleAlpha,
-                    RGB if has_trns => RGBA,
-                    Indexed if has_trns => RGBA,
-                    Indexed => RGB,
-                    ct => ct,
-                }
-            } else {
-                info.color_type
-            };
-            (color_type, BitDepth::from_u8(bits).unwrap())
+            transform::output_layout(info, self.transform)
         }
     }
 
     /// Returns the number of bytes required to hold a deinterlaced image frame
     /// that is decoded using the given input transformations.
     pub fn output_buffer_size(&self) -> usize {
-        let (width, height) = get_info!(self).size();
+        let (width, height) = self.output_size();
         let size = self.output_line_size(width);
         size * height as usize
     }
 
+    /// The orientation `next_frame` applies to frames.
+    fn orientation(&self) -> Orientation {
+        if self.transform.contains(Transformations::ORIENTATION) {
+            get_info!(self)
+                .orientation()
+                .unwrap_or(Orientation::TopLeft)
+        } else {
+            Orientation::TopLeft
+        }
+    }
+
+    /// The width and height of frames output by `next_frame`.
+    fn output_size(&self) -> (u32, u32) {
+        let (width, height) = get_info!(self).size();
+        if self.orientation().transposes() {
+            (height, width)
+        } else {
+            (width, height)
+        }
+    }
+
     fn validate_buffer_sizes(&self) -> Result<(), DecodingError> {
         // Check if the decoding buffer of a single raw line has a valid size.
         if self.info().checked_raw_row_length().is_none() {
@@ -602,7 +1085,7 @@ impl<R: Read> Reader<R> {
     }
 
     fn checked_output_buffer_size(&self) -> Option<usize> {
-        let (width, height) = get_info!(self).size();
+        let (width, height) = self.output_size();
         let (color, depth) = self.imm_output_color_type();
         let rowlen = color.checked_raw_row_length(depth, width)? - 1;
         let height: usize = std::convert::TryFrom::try_from(height).ok()?;
@@ -617,30 +1100,7 @@ impl<R: Read> Reader<R> {
 
     /// Returns the number of bytes required to decode a deinterlaced row.
     fn line_size(&self, width: u32) -> Option<usize> {
-        use crate::common::ColorType::*;
-        let t = self.transform;
-        let info = get_info!(self);
-        let trns = info.trns.is_some();
-
-        let expanded = if info.bit_depth == BitDepth::Sixteen {
-            BitDepth::Sixteen
-        } else {
-            BitDepth::Eight
-        };
-        // The color type and depth representing the decoded line
-        // TODO 16 bit
-        let (color, depth) = match info.color_type {
-            Indexed if trns && t.contains(Transformations::EXPAND) => (RGBA, expanded),
-            Indexed if t.contains(Transformations::EXPAND) => (RGB, expanded),
-            RGB if trns && t.contains(Transformations::EXPAND) => (RGBA, expanded),
-            Grayscale if trns && t.contains(Transformations::EXPAND) => (GrayscaleAlpha, expanded),
-            Grayscale if t.contains(Transformations::EXPAND) => (Grayscale, expanded),
-            GrayscaleAlpha if t.contains(Transformations::EXPAND) => (GrayscaleAlpha, expanded),
-            other => (other, info.bit_depth),
-        };
-
-        // Without the filter method byte
-        color.checked_raw_row_length(depth, width).map(|n| n - 1)
+        transform::buffer_line_size(get_info!(self), self.transform, width)
     }
 
     fn allocate_out_buf(&mut self) -> Result<(), DecodingError> {
@@ -655,7 +1115,7 @@ impl<R: Read> Reader<R> {
         Ok(())
     }
 
-    fn next_pass(&mut self) -> Option<(usize, InterlaceInfo)> {
+    fn next_row_info(&mut self) -> Option<(usize, InterlaceInfo)> {
         match self.subframe.interlace {
             InterlaceIter::Adam7(ref mut adam7) => {
                 let last_pass = adam7.current_pass();
@@ -678,7 +1138,7 @@ impl<R: Read> Reader<R> {
     /// The scanline is filtered against the previous scanline according to the specification.
     fn next_raw_interlaced_row(&mut self) -> Result<Option<InterlacedRow<'_>>, DecodingError> {
         let bpp = self.bpp;
-        let (rowlen, passdata) = match self.next_pass() {
+        let (rowlen, passdata) = match self.next_row_info() {
             Some((rowlen, passdata)) => (rowlen, passdata),
             None => return Ok(None),
         };
@@ -775,73 +1235,39 @@ impl SubframeInfo {
     }
 }
 
-fn expand_paletted(buffer: &mut [u8], info: &Info) -> Result<(), DecodingError> {
-    if let Some(palette) = info.palette.as_ref() {
-        if let BitDepth::Sixteen = info.bit_depth {
-            Err(DecodingError::Format(
-                "Bit depth '16' is not valid for paletted images".into(),
-            ))
-        } else {
-            let black = [0, 0, 0];
-            if let Some(ref trns) = info.trns {
-                utils::unpack_bits(buffer, 4, info.bit_depth as u8, |i, chunk| {
-                    let (rgb, a) = (
-                        palette
-                            .get(3 * i as usize..3 * i as usize + 3)
-                            .unwrap_or(&black),
-                        *trns.get(i as usize).unwrap_or(&0xFF),
-                    );
-                    chunk[0] = rgb[0];
-                    chunk[1] = rgb[1];
-                    chunk[2] = rgb[2];
-                    chunk[3] = a;
-                });
-            } else {
-                utils::unpack_bits(buffer, 3, info.bit_depth as u8, |i, chunk| {
-                    let rgb = palette
-                        .get(3 * i as usize..3 * i as usize + 3)
-                        .unwrap_or(&black);
-                    chunk[0] = rgb[0];
-                    chunk[1] = rgb[1];
-                    chunk[2] = rgb[2];
-                })
-            }
-            Ok(())
-        }
-    } else {
-        Err(DecodingError::Format("missing palette".into()))
-    }
-}
-
-fn expand_gray_u8(buffer: &mut [u8], info: &Info) {
-    let rescale = true;
-    let scaling_factor = if rescale {
-        (255) / ((1u16 << info.bit_depth as u8) - 1) as u8
-    } else {
-        1
-    };
-    if let Some(ref trns) = info.trns {
-        utils::unpack_bits(buffer, 2, info.bit_depth as u8, |pixel, chunk| {
-            if pixel == trns[0] {
-                chunk[1] = 0
-            } else {
-                chunk[1] = 0xFF
-            }
-            chunk[0] = pixel * scaling_factor
-        })
-    } else {
-        utils::unpack_bits(buffer, 1, info.bit_depth as u8, |val, chunk| {
-            chunk[0] = val * scaling_factor
-        })
-    }
-}
-
 #[cfg(test)]
 mod tests {
-    use super::Decoder;
+    use super::{Decoder, OutputInfo, Reader};
+    use crate::{ColorType, Transformations};
+    use std::fs::File;
     use std::io::{BufRead, Read, Result};
     use std::mem::discriminant;
 
+    /// The path of the image `name` of the PngSuite.
+    fn pngsuite_path(name: &str) -> String {
+        format!("{}/tests/pngsuite/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    /// A decoder of the image `name` of the PngSuite with the transformations `t`.
+    fn pngsuite_decoder(name: &str, t: Transformations) -> Decoder<File> {
+        let mut decoder = Decoder::new(File::open(pngsuite_path(name)).unwrap());
+        decoder.set_transformations(t);
+        decoder
+    }
+
+    /// Reads the info of the image `name` of the PngSuite, see `pngsuite_decoder`.
+    fn open_pngsuite(name: &str, t: Transformations) -> (OutputInfo, Reader<File>) {
+        pngsuite_decoder(name, t).read_info().unwrap()
+    }
+
+    /// Decodes the first frame of an image with the settings of `decoder`.
+    fn decode_frame(decoder: Decoder<File>) -> (OutputInfo, Vec<u8>) {
+        let (info, mut reader) = decoder.read_info().unwrap();
+        let mut buf = vec![0; info.buffer_size()];
+        reader.next_frame(&mut buf).unwrap();
+        (info, buf)
+    }
+
     /// A reader that reads at most `n` bytes.
     struct SmalBuf<R: BufRead> {
         inner: R,
@@ -895,4 +1321,536 @@ mod tests {
 
         assert_eq!(discriminant(&normal), discriminant(&smal));
     }
+
+    #[test]
+    fn transformed_row_sizes() {
+        let transformations = [
+            Transformations::EXPAND | Transformations::STRIP_ALPHA | Transformations::STRIP_16,
+            Transformations::PACKING | Transformations::PACKSWAP | Transformations::INVERT_MONO,
+            Transformations::EXPAND_16 | Transformations::GRAY_TO_RGB | Transformations::BGR,
+            Transformations::SCALE_16 | Transformations::SWAP_ALPHA | Transformations::INVERT_ALPHA,
+            Transformations::GRAY_TO_RGB | Transformations::SWAP_ENDIAN,
+        ];
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/pngsuite");
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let name = path.file_name().unwrap().to_str().unwrap();
+            // Images starting with `x` are corrupt.
+            if !name.ends_with(".png") || name.starts_with('x') {
+                continue;
+            }
+            for &t in transformations.iter() {
+                let mut decoder = Decoder::new(std::fs::File::open(&path).unwrap());
+                decoder.set_transformations(t);
+                let (info, mut reader) = decoder.read_info().unwrap();
+                assert_eq!(info.line_size, reader.output_line_size(info.width));
+                let mut size = 0;
+                while let Some((row, adam7)) = reader.next_interlaced_row().unwrap() {
+                    let width = adam7.map_or(info.width, |(_, _, width)| width);
+                    assert_eq!(
+                        row.len(),
+                        info.color_type
+                            .raw_row_length_from_width(info.bit_depth, width)
+                            - 1,
+                        "{} {:?}",
+                        name,
+                        t
+                    );
+                    size += row.len();
+                }
+                if !reader.info().interlaced {
+                    assert_eq!(size, info.buffer_size(), "{} {:?}", name, t);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn scale_16_pngsuite() {
+        // Rows 0 and 16 of basn0g16.png, rounded to 8 bits and truncated to their high byte.
+        const SCALED: [[u8; 32]; 2] = [
+            [
+                0, 9, 18, 27, 36, 45, 54, 63, 72, 81, 90, 99, 108, 117, 126, 134, 143, 152, 161,
+                170, 179, 188, 197, 206, 215, 224, 233, 242, 251, 240, 213, 186,
+            ],
+            [
+                32, 41, 50, 59, 68, 77, 86, 95, 104, 113, 122, 130, 139, 148, 157, 166, 175, 184,
+                193, 202, 211, 220, 229, 238, 247, 252, 225, 198, 171, 144, 118, 91,
+            ],
+        ];
+        const STRIPPED: [[u8; 32]; 2] = [
+            [
+                0, 9, 18, 27, 36, 45, 54, 63, 72, 81, 90, 99, 108, 117, 126, 135, 144, 153, 162,
+                171, 180, 189, 198, 207, 216, 225, 234, 243, 252, 240, 213, 186,
+            ],
+            [
+                32, 41, 50, 59, 68, 77, 86, 95, 104, 113, 122, 131, 140, 149, 158, 167, 176, 185,
+                194, 203, 212, 221, 230, 239, 248, 252, 225, 198, 171, 144, 117, 90,
+            ],
+        ];
+        let decode = |t: Option<Transformations>| {
+            let decoder = match t {
+                Some(t) => pngsuite_decoder("basn0g16.png", t),
+                None => Decoder::new(File::open(pngsuite_path("basn0g16.png")).unwrap()),
+            };
+            decode_frame(decoder).1
+        };
+        let both = Transformations::SCALE_16 | Transformations::STRIP_16;
+        for &(t, expected) in [
+            (None, &SCALED),
+            (Some(Transformations::SCALE_16), &SCALED),
+            (Some(both), &SCALED),
+            (Some(Transformations::STRIP_16), &STRIPPED),
+        ]
+        .iter()
+        {
+            let buf = decode(t);
+            assert_eq!(&buf[..32], &expected[0][..], "{:?}", t);
+            assert_eq!(&buf[16 * 32..17 * 32], &expected[1][..], "{:?}", t);
+        }
+    }
+
+    #[test]
+    fn gamma_correction() {
+        let decode = |name: &str, t, screen_gamma: Option<f64>| {
+            let mut decoder = pngsuite_decoder(name, t);
+            if let Some(screen_gamma) = screen_gamma {
+                decoder.set_gamma_correction(screen_gamma);
+            }
+            decode_frame(decoder).1
+        };
+
+        // A file gamma of 1.0 displayed with a screen gamma of 2.2.
+        let raw = decode("g10n2c08.png", Transformations::IDENTITY, None);
+        let corrected = decode("g10n2c08.png", Transformations::IDENTITY, Some(2.2));
+        for (&raw, &corrected) in raw.iter().zip(corrected.iter()) {
+            let expected = (f64::from(raw) / 255.0).powf(1.0 / 2.2) * 255.0;
+            assert_eq!(corrected, expected.round() as u8);
+        }
+        assert!(raw != corrected);
+
+        // Matching file and screen gamma leave the samples unchanged, also for palettes and
+        // 16-bit samples.
+        for &(name, screen_gamma) in [
+            ("g10n3p04.png", 1.0),
+            ("g25n3p04.png", 0.4),
+            ("g25n0g16.png", 0.4),
+            ("basn6a08.png", 1.0),
+            // Without gAMA chunk the gamma defaults to 1/2.2.
+            ("z09n2c08.png", 2.2),
+        ]
+        .iter()
+        {
+            let t = Transformations::EXPAND;
+            assert!(
+                decode(name, t, None) == decode(name, t, Some(screen_gamma)),
+                "{}",
+                name
+            );
+        }
+
+        // Alpha is not corrected.
+        let raw = decode("basn6a08.png", Transformations::IDENTITY, None);
+        let corrected = decode("basn6a08.png", Transformations::IDENTITY, Some(2.2));
+        for (raw, corrected) in raw.chunks(4).zip(corrected.chunks(4)) {
+            assert_eq!(raw[3], corrected[3]);
+            let expected = (f64::from(raw[0]) / 255.0).powf(1.0 / 2.2) * 255.0;
+            assert_eq!(corrected[0], expected.round() as u8);
+        }
+
+        // Unpacked samples of less than 8 bits are not corrected unless they are rescaled.
+        let packed = decode("basn0g04.png", Transformations::PACKING, None);
+        let corrected = decode("basn0g04.png", Transformations::PACKING, Some(2.2));
+        assert!(packed == corrected);
+        let expanded = decode("basn0g04.png", Transformations::EXPAND, None);
+        let corrected = decode("basn0g04.png", Transformations::EXPAND, Some(2.2));
+        for (&raw, &corrected) in expanded.iter().zip(corrected.iter()) {
+            let expected = (f64::from(raw) / 255.0).powf(1.0 / 2.2) * 255.0;
+            assert_eq!(corrected, expected.round() as u8);
+        }
+
+        // The palette is corrected once.
+        let expanded = decode("g10n3p04.png", Transformations::EXPAND, None);
+        let corrected = decode("g10n3p04.png", Transformations::EXPAND, Some(2.2));
+        for (&raw, &corrected) in expanded.iter().zip(corrected.iter()) {
+            let expected = (f64::from(raw) / 255.0).powf(1.0 / 2.2) * 255.0;
+            assert_eq!(corrected, expected.round() as u8);
+        }
+    }
+
+    #[test]
+    fn shift_pngsuite() {
+        let decode = |name: &str, t| decode_frame(pngsuite_decoder(name, t)).1;
+
+        let raw = decode("cs3n2c16.png", Transformations::IDENTITY);
+        let shifted = decode("cs3n2c16.png", Transformations::SHIFT);
+        for (raw, shifted) in raw.chunks(2).zip(shifted.chunks(2)) {
+            let raw = u16::from_be_bytes([raw[0], raw[1]]);
+            assert_eq!(u16::from_be_bytes([shifted[0], shifted[1]]), raw >> 3);
+        }
+
+        let raw = decode("cs3n3p08.png", Transformations::EXPAND);
+        let shifted = decode(
+            "cs3n3p08.png",
+            Transformations::EXPAND | Transformations::SHIFT,
+        );
+        for (&raw, &shifted) in raw.iter().zip(shifted.iter()) {
+            assert_eq!(shifted, raw >> 5);
+        }
+    }
+
+    #[test]
+    fn progressive_passes_pngsuite() {
+        use super::InterlaceHandling;
+        let open_with = |name: &str, t, handling| {
+            let mut decoder = pngsuite_decoder(name, t);
+            decoder.set_interlace_handling(handling);
+            decoder.read_info().unwrap()
+        };
+        let open = |name: &str, handling| open_with(name, Transformations::IDENTITY, handling);
+        let files = [
+            "0g01", "0g02", "0g04", "0g08", "0g16", "2c08", "2c16", "3p01", "3p02", "3p04", "3p08",
+            "4a08", "4a16", "6a08", "6a16",
+        ];
+        // Sub-byte pixels are also placed in the order of PACKSWAP.
+        for &t in [Transformations::IDENTITY, Transformations::PACKSWAP].iter() {
+            for file in files.iter() {
+                let name = format!("basn{}.png", file);
+                let (info, mut reader) = open_with(&name, t, InterlaceHandling::Sparkle);
+                let mut expected = vec![0; info.buffer_size()];
+                reader.next_frame(&mut expected).unwrap();
+
+                for &handling in [InterlaceHandling::Rectangle, InterlaceHandling::Sparkle].iter() {
+                    let (info, mut reader) = open_with(&format!("basi{}.png", file), t, handling);
+                    let mut buf = vec![0; info.buffer_size()];
+                    let mut passes = vec![];
+                    while let Some(pass) = reader.next_pass(&mut buf).unwrap() {
+                        passes.push(pass);
+                    }
+                    assert_eq!(passes, [1, 2, 3, 4, 5, 6, 7]);
+                    assert_eq!(buf, expected, "basi{}.png {:?} {:?}", file, t, handling);
+                }
+            }
+        }
+
+        // The pixels of the first pass cover 8x8 blocks, the raw rows hold one pixel per block.
+        let (info, mut reader) = open("basn0g08.png", InterlaceHandling::Sparkle);
+        let mut expected = vec![0; info.buffer_size()];
+        assert_eq!(reader.next_pass(&mut expected).unwrap(), Some(7));
+        assert_eq!(reader.next_pass(&mut expected).unwrap(), None);
+        let (_, mut reader) = open("basi0g08.png", InterlaceHandling::Rectangle);
+        let mut buf = vec![0; info.buffer_size()];
+        assert_eq!(reader.next_pass(&mut buf).unwrap(), Some(1));
+        for row in buf.chunks(32).take(8) {
+            assert!(row[..8].iter().all(|&gray| gray == expected[0]));
+            assert!(row[24..].iter().all(|&gray| gray == expected[24]));
+        }
+        let (_, mut reader) = open("basi0g08.png", InterlaceHandling::RawRows);
+        assert_eq!(reader.next_pass(&mut buf).unwrap(), Some(1));
+        let pixels = [0, 8, 16, 24]
+            .iter()
+            .map(|&x| expected[x])
+            .collect::<Vec<_>>();
+        assert_eq!(buf[..4], pixels[..]);
+    }
+
+    #[test]
+    fn preview_pngsuite() {
+        let pixel = |buf: &[u8], line_size: usize, bits: usize, t, (x, y): (usize, usize)| {
+            let line = &buf[y * line_size..];
+            if bits < 8 {
+                let bit = x * bits;
+                let shift = if t == Transformations::PACKSWAP {
+                    bit % 8
+                } else {
+                    8 - bit % 8 - bits
+                };
+                vec![(line[bit / 8] >> shift) & ((1 << bits) - 1)]
+            } else {
+                line[x * bits / 8..(x + 1) * bits / 8].to_vec()
+            }
+        };
+        // The odd size leaves partial blocks at the right and bottom edge.
+        let files = ["0g01", "0g04", "0g16", "2c08", "3p02", "6a16"]
+            .iter()
+            .map(|file| (format!("basn{}.png", file), format!("basi{}.png", file)))
+            .chain(Some((
+                "s35n3p04.png".to_string(),
+                "s35i3p04.png".to_string(),
+            )));
+        for (name, interlaced) in files {
+            // Sub-byte pixels are also placed in the order of PACKSWAP.
+            for &t in [Transformations::IDENTITY, Transformations::PACKSWAP].iter() {
+                let (info, mut reader) = open_pngsuite(&name, t);
+                let mut full = vec![0; info.buffer_size()];
+                reader.next_frame(&mut full).unwrap();
+                let bits = info.color_type.samples() * info.bit_depth as usize;
+
+                for max_pass in 1..=7 {
+                    let (_, mut reader) = open_pngsuite(&interlaced, t);
+                    let mut preview = vec![0; reader.preview_buffer_size(max_pass)];
+                    let (width, height) = reader.decode_preview(max_pass, &mut preview).unwrap();
+                    let (x_step, y_step) = crate::utils::adam7_block(max_pass);
+                    let blocks = |len: u32, step: usize| (len - 1) / step as u32 + 1;
+                    assert_eq!(
+                        (width, height),
+                        (blocks(info.width, x_step), blocks(info.height, y_step))
+                    );
+                    let preview_line_size = reader.output_line_size(width);
+                    for y in 0..height as usize {
+                        for x in 0..width as usize {
+                            assert_eq!(
+                                pixel(&preview, preview_line_size, bits, t, (x, y)),
+                                pixel(&full, info.line_size, bits, t, (x * x_step, y * y_step)),
+                            );
+                        }
+                    }
+                    assert!(reader.next_frame(&mut full).is_err());
+                    reader.finish().unwrap();
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn frame_region_pngsuite() {
+        let transformations = [
+            Transformations::IDENTITY,
+            Transformations::EXPAND | Transformations::GRAY_TO_RGB | Transformations::STRIP_16,
+            Transformations::PACKSWAP,
+        ];
+        for name in [
+            "basn0g01", "basn0g04", "basn3p02", "basn6a16", "basi0g01", "basi3p04", "basi2c08",
+        ]
+        .iter()
+        {
+            for &t in transformations.iter() {
+                let (info, mut reader) = open_pngsuite(&format!("{}.png", name), t);
+                let mut full = vec![0; info.buffer_size()];
+                reader.next_frame(&mut full).unwrap();
+                let bits = info.color_type.samples() * info.bit_depth as usize;
+
+                let (x, y, width, height) = (3, 5, 21, 9);
+                let (_, mut reader) = open_pngsuite(&format!("{}.png", name), t);
+                let line_size = reader.output_line_size(width);
+                let mut region = vec![0; line_size * height as usize];
+                reader
+                    .next_frame_region(&mut region, (x, y), (width, height))
+                    .unwrap();
+                let mut expected = vec![0; region.len()];
+                for (row, out) in full
+                    .chunks(info.line_size)
+                    .skip(y as usize)
+                    .zip(expected.chunks_mut(line_size))
+                {
+                    let lsb_first = t == Transformations::PACKSWAP;
+                    crate::utils::crop_line(
+                        row,
+                        (x as usize, width as usize),
+                        bits,
+                        lsb_first,
+                        out,
+                    );
+                }
+                assert_eq!(region, expected, "{} {:?}", name, t);
+            }
+        }
+
+        let (_, mut reader) = open_pngsuite("basn0g08.png", Transformations::IDENTITY);
+        let mut region = vec![0; 32 * 32];
+        assert!(reader
+            .next_frame_region(&mut region, (1, 0), (32, 1))
+            .is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "png-encoding")]
+    fn frame_region_interlaced_subframe() {
+        // A subframe smaller than the image is decoded completely before it is cropped.
+        let mut png = Vec::new();
+        {
+            let mut encoder = crate::Encoder::new(&mut png, 4, 4);
+            encoder.set_color(ColorType::Grayscale);
+            encoder.set_interlaced(true);
+            encoder.set_animated(2, 0).unwrap();
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_image_data(&[0; 16]).unwrap();
+            writer.set_frame_dimension(2, 3).unwrap();
+            writer.set_frame_position(1, 1).unwrap();
+            writer.write_image_data(&[1, 2, 3, 4, 5, 6]).unwrap();
+        }
+        let mut decoder = Decoder::new(&png[..]);
+        decoder.set_transformations(Transformations::IDENTITY);
+        let (info, mut reader) = decoder.read_info().unwrap();
+        let mut buf = vec![0; info.buffer_size()];
+        reader.next_frame(&mut buf).unwrap();
+        let mut region = [0; 2];
+        reader
+            .next_frame_region(&mut region, (1, 1), (1, 2))
+            .unwrap();
+        assert_eq!(region, [4, 6]);
+    }
+
+    #[test]
+    fn strided_frame_pngsuite() {
+        // Sub-byte pixels of interlaced images are also placed in the order of PACKSWAP.
+        let open = |name: &str| open_pngsuite(name, Transformations::PACKSWAP);
+        for name in ["basn0g01", "basn3p04", "basn6a16", "basi0g02", "basi2c08"].iter() {
+            let (info, mut reader) = open(&format!("{}.png", name));
+            let mut full = vec![0; info.buffer_size()];
+            reader.next_frame(&mut full).unwrap();
+            let not_interlaced = name.replace("basi", "basn");
+            let (_, mut reader) = open(&format!("{}.png", not_interlaced));
+            let mut expected = vec![0; info.buffer_size()];
+            reader.next_frame(&mut expected).unwrap();
+            assert_eq!(full, expected, "{}", name);
+
+            let stride = info.line_size + 5;
+            let (_, mut reader) = open(&format!("{}.png", name));
+            let mut strided = vec![0xA5; stride * info.height as usize];
+            assert!(reader
+                .next_frame_strided(&mut strided, info.line_size - 1)
+                .is_err());
+            reader.next_frame_strided(&mut strided, stride).unwrap();
+            for (row, out) in full.chunks(info.line_size).zip(strided.chunks(stride)) {
+                assert_eq!(row, &out[..info.line_size], "{}", name);
+                assert!(out[info.line_size..].iter().all(|&b| b == 0xA5));
+            }
+        }
+
+        // A reoriented sub-byte frame whose rows of 3 pixels become 3 rows of 2 pixels.
+        #[cfg(feature = "png-encoding")]
+        {
+            let mut exif = b"II*\0\x08\0\0\0\x01\0".to_vec();
+            exif.extend_from_slice(&[0x12, 0x01, 3, 0, 1, 0, 0, 0, 6, 0, 0, 0]);
+            exif.extend_from_slice(&[0; 4]);
+            let mut png = Vec::new();
+            {
+                let mut encoder = crate::Encoder::new(&mut png, 3, 2);
+                encoder.set_color(ColorType::Grayscale);
+                encoder.set_depth(crate::BitDepth::One);
+                encoder.set_exif_metadata(exif);
+                let mut writer = encoder.write_header().unwrap();
+                writer
+                    .write_image_data(&[0b1010_0000, 0b0110_0000])
+                    .unwrap();
+            }
+            let mut decoder = Decoder::new(&png[..]);
+            decoder.set_transformations(Transformations::ORIENTATION);
+            let (info, mut reader) = decoder.read_info().unwrap();
+            assert_eq!((info.width, info.height), (2, 3));
+            let mut strided = vec![0xA5; 4 * 3];
+            reader.next_frame_strided(&mut strided, 4).unwrap();
+            assert_eq!(
+                strided,
+                [0x40, 0xA5, 0xA5, 0xA5, 0x80, 0xA5, 0xA5, 0xA5, 0xC0, 0xA5, 0xA5, 0xA5]
+            );
+        }
+    }
+
+    #[test]
+    fn frame_on_canvas_apng() {
+        let path = format!(
+            "{}/tests/animated/basic_f20.png",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        let open = || {
+            let mut decoder = Decoder::new(std::fs::File::open(&path).unwrap());
+            decoder.set_transformations(Transformations::IDENTITY);
+            decoder.read_info().unwrap()
+        };
+        let (info, mut reader) = open();
+        let (_, mut canvas_reader) = open();
+        let frames = reader.info().animation_control.unwrap().num_frames;
+        let stride = info.line_size + 3;
+        let mut canvas = vec![0; stride * info.height as usize];
+        for _ in 0..frames {
+            let mut frame = vec![0; info.buffer_size()];
+            reader.next_frame(&mut frame).unwrap();
+            canvas_reader
+                .next_frame_on_canvas(&mut canvas, stride)
+                .unwrap();
+            let fc = reader.info().frame_control.unwrap();
+            let bytes_pp = reader.info().bytes_per_pixel();
+            let line_size = fc.width as usize * bytes_pp;
+            let x = fc.x_offset as usize * bytes_pp;
+            let rows = canvas.chunks(stride).skip(fc.y_offset as usize);
+            for (row, out) in frame.chunks(line_size).zip(rows).take(fc.height as usize) {
+                assert_eq!(row, &out[x..x + line_size]);
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "png-encoding")]
+    fn frame_on_canvas_subbyte() {
+        // A 1-bit subframe of 3 pixels whose right edge lies within a byte of the canvas.
+        let mut png = Vec::new();
+        {
+            let mut encoder = crate::Encoder::new(&mut png, 16, 2);
+            encoder.set_color(ColorType::Grayscale);
+            encoder.set_depth(crate::BitDepth::One);
+            encoder.set_animated(2, 0).unwrap();
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_image_data(&[0xFF; 4]).unwrap();
+            writer.set_frame_dimension(3, 2).unwrap();
+            writer.set_frame_position(8, 0).unwrap();
+            writer.write_image_data(&[0x40, 0x40]).unwrap();
+        }
+        let mut decoder = Decoder::new(&png[..]);
+        decoder.set_transformations(Transformations::IDENTITY);
+        let (_, mut reader) = decoder.read_info().unwrap();
+        let mut canvas = vec![0xAA; 3 * 2];
+        reader.next_frame_on_canvas(&mut canvas, 3).unwrap();
+        reader.next_frame_on_canvas(&mut canvas, 3).unwrap();
+        assert_eq!(canvas, [0xFF, 0x5F, 0xAA, 0xFF, 0x5F, 0xAA]);
+    }
+
+    #[test]
+    fn background_pngsuite() {
+        let decode = |name: &str, t, rgb: Option<(u16, u16, u16)>| {
+            let mut decoder = pngsuite_decoder(name, t);
+            if let Some(rgb) = rgb {
+                decoder.set_background_color(rgb);
+            }
+            let (info, buf) = decode_frame(decoder);
+            (info.color_type, buf)
+        };
+
+        // A color key with a red bKGD, or a blue background set by the caller.
+        let (_, rgba) = decode("tbrn2c08.png", Transformations::EXPAND, None);
+        for &(rgb, background) in
+            [(None, [0xFF, 0, 0]), (Some((0, 0, 0xFFFF)), [0, 0, 0xFF])].iter()
+        {
+            let (color, composited) = decode("tbrn2c08.png", Transformations::BACKGROUND, rgb);
+            assert_eq!(color, ColorType::RGB);
+            for (rgba, rgb) in rgba.chunks(4).zip(composited.chunks(3)) {
+                let expected = if rgba[3] == 0 {
+                    &background[..]
+                } else {
+                    &rgba[..3]
+                };
+                assert_eq!(rgb, expected);
+            }
+        }
+
+        // Sub-byte grayscale with a color key is expanded.
+        let (_, gray_alpha) = decode("tbbn0g04.png", Transformations::EXPAND, None);
+        let (color, composited) = decode("tbbn0g04.png", Transformations::BACKGROUND, None);
+        assert_eq!(color, ColorType::Grayscale);
+        for (ga, &gray) in gray_alpha.chunks(2).zip(composited.iter()) {
+            assert_eq!(gray, if ga[1] == 0 { 0 } else { ga[0] });
+        }
+
+        // Partial transparency onto a white bKGD.
+        let (_, rgba) = decode("bgwn6a08.png", Transformations::IDENTITY, None);
+        let (_, composited) = decode("bgwn6a08.png", Transformations::BACKGROUND, None);
+        for (rgba, rgb) in rgba.chunks(4).zip(composited.chunks(3)) {
+            let alpha = u32::from(rgba[3]);
+            for (&fore, &out) in rgba[..3].iter().zip(rgb.iter()) {
+                let expected = (u32::from(fore) * alpha + 255 * (255 - alpha) + 127) / 255;
+                assert_eq!(u32::from(out), expected);
+            }
+        }
+    }
 }
@@ -11,16 +11,35 @@ use std::io;
 use crc32fast::Hasher as Crc32;
 
 use super::zlib::ZlibStream;
+use super::Limits;
 use crate::chunk::{self, ChunkType, IDAT, IEND, IHDR};
 use crate::common::{
-    AnimationControl, BitDepth, BlendOp, ColorType, DisposeOp, FrameControl, Info, PixelDimensions,
-    ScaledFloat, SourceChromaticities, Unit,
+    parse_ascii_float, AnimationControl, BackgroundColor, BitDepth, BlendOp, ChunkPosition,
+    CodingIndependentCodePoints, ColorType, ContentLightLevelInfo, DisposeOp, EquationType,
+    FrameControl, IccProfile, ImageOffset, Info, MasteringDisplayColorVolume, OffsetUnit,
+    PhysicalScale, PixelCalibration, PixelDimensions, ScaleUnit, ScaledFloat, SourceChromaticities,
+    SrgbRenderingIntent, StereoLayout, SuggestedPalette, SuggestedPaletteEntry, Time, Unit,
+    UnknownChunk,
 };
+use crate::text_metadata::{decode_iso_8859_1, decompress_zlib, ITXtChunk, TEXtChunk, ZTXtChunk};
 use crate::traits::ReadBytesExt;
 
 /// TODO check if these size are reasonable
 pub const CHUNCK_BUFFER_SIZE: usize = 32 * 1024;
 
+/// Ancillary chunks of variable length that are parsed as a whole. Their data is buffered
+/// completely, within the limits, instead of in parts of `CHUNCK_BUFFER_SIZE`.
+const WHOLE_CHUNKS: &[ChunkType] = &[
+    chunk::tEXt,
+    chunk::zTXt,
+    chunk::iTXt,
+    chunk::iCCP,
+    chunk::eXIf,
+    chunk::sPLT,
+    chunk::pCAL,
+    chunk::sCAL,
+];
+
 /// Determines if checksum checks should be disabled globally.
 ///
 /// This is used only in fuzzing. `afl` automatically adds `--cfg fuzzing` to RUSTFLAGS which can
@@ -58,6 +77,12 @@ pub enum Decoded {
     PixelDimensions(PixelDimensions),
     AnimationControl(AnimationControl),
     FrameControl(FrameControl),
+    /// A tEXt chunk was decoded, it has also been appended to the `Info`.
+    TEXtChunk(TEXtChunk),
+    /// A zTXt chunk was decoded and its text inflated, it has also been appended to the `Info`.
+    ZTXtChunk(ZTXtChunk),
+    /// An iTXt chunk was decoded, it has also been appended to the `Info`.
+    ITXtChunk(ITXtChunk),
     /// Decoded raw image data.
     ImageData,
     /// The last of a consecutive chunk of IDAT was done.
@@ -144,6 +169,10 @@ pub struct StreamingDecoder {
     /// Stores where in decoding an `fdAT` chunk we are.
     apng_seq_handled: bool,
     have_idat: bool,
+    /// Limits on the memory used for decompressing ancillary chunks.
+    limits: Limits,
+    /// Whether to collect ancillary chunks that are not parsed into `Info::unknown_chunks`.
+    preserve_unknown_chunks: bool,
 }
 
 struct ChunkState {
@@ -174,9 +203,25 @@ impl StreamingDecoder {
             current_seq_no: None,
             apng_seq_handled: false,
             have_idat: false,
+            limits: Limits::default(),
+            preserve_unknown_chunks: false,
         }
     }
 
+    /// Limit the memory used for decompressing text chunks.
+    ///
+    /// The budget is shared by all compressed chunks of the image. It is not restored by `reset`.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Collect the ancillary chunks not known to the decoder into `Info::unknown_chunks`.
+    ///
+    /// Their data counts towards the `Limits` of the decoder. It is not restored by `reset`.
+    pub fn set_preserve_unknown_chunks(&mut self, preserve: bool) {
+        self.preserve_unknown_chunks = preserve;
+    }
+
     /// Resets the StreamingDecoder
     pub fn reset(&mut self) {
         self.state = Some(State::Signature(0, [0; 7]));
@@ -375,9 +420,15 @@ impl StreamingDecoder {
             }
             ReadChunk(type_str, clear) => {
                 if clear {
-                    self.current_chunk.raw_bytes.clear();
+                    if self.current_chunk.raw_bytes.capacity() > CHUNCK_BUFFER_SIZE {
+                        self.current_chunk.raw_bytes = Vec::with_capacity(CHUNCK_BUFFER_SIZE);
+                    } else {
+                        self.current_chunk.raw_bytes.clear();
+                    }
                 }
                 if self.current_chunk.remaining > 0 {
+                    let whole_chunk = WHOLE_CHUNKS.contains(&type_str)
+                        || (self.preserve_unknown_chunks && !chunk::is_critical(type_str));
                     let ChunkState {
                         crc,
                         remaining,
@@ -387,7 +438,15 @@ impl StreamingDecoder {
                     let buf_avail = raw_bytes.capacity() - raw_bytes.len();
                     let bytes_avail = min(buf.len(), buf_avail);
                     let n = min(*remaining, bytes_avail as u32);
-                    if buf_avail == 0 {
+                    if buf_avail == 0 && whole_chunk {
+                        // Grow the buffer to hold the whole chunk within the limits.
+                        let needed = raw_bytes.len().saturating_add(*remaining as usize);
+                        if needed > self.limits.bytes {
+                            return Err(DecodingError::LimitsExceeded);
+                        }
+                        raw_bytes.reserve_exact(*remaining as usize);
+                        goto!(0, ReadChunk(type_str, false))
+                    } else if buf_avail == 0 {
                         goto!(0, PartialChunk(type_str))
                     } else {
                         let buf = &buf[..n as usize];
@@ -400,6 +459,9 @@ impl StreamingDecoder {
                             goto!(n as usize, ReadChunk(type_str, false))
                         }
                     }
+                } else if clear && type_str != IDAT && type_str != chunk::fdAT {
+                    // An empty chunk, which is parsed like any other.
+                    goto!(0, PartialChunk(type_str))
                 } else {
                     goto!(0, U32(U32Value::Crc(type_str)))
                 }
@@ -446,7 +508,25 @@ impl StreamingDecoder {
             chunk::acTL => self.parse_actl(),
             chunk::fcTL => self.parse_fctl(),
             chunk::cHRM => self.parse_chrm(),
-            _ => Ok(Decoded::PartialChunk(type_str)),
+            chunk::iCCP => self.parse_iccp(),
+            chunk::sRGB => self.parse_srgb(),
+            chunk::sBIT => self.parse_sbit(),
+            chunk::bKGD => self.parse_bkgd(),
+            chunk::hIST => self.parse_hist(),
+            chunk::sPLT => self.parse_splt(),
+            chunk::tIME => self.parse_time(),
+            chunk::eXIf => self.parse_exif(),
+            chunk::cICP => self.parse_cicp(),
+            chunk::mDCv => self.parse_mdcv(),
+            chunk::cLLi => self.parse_clli(),
+            chunk::oFFs => self.parse_offs(),
+            chunk::pCAL => self.parse_pcal(),
+            chunk::sCAL => self.parse_scal(),
+            chunk::sTER => self.parse_ster(),
+            chunk::tEXt => self.parse_text(),
+            chunk::zTXt => self.parse_ztxt(),
+            chunk::iTXt => self.parse_itxt(),
+            _ => self.parse_unknown(type_str),
         } {
             Err(err) => {
                 // Borrow of self ends here, because Decoding error does not borrow self.
@@ -463,6 +543,31 @@ impl StreamingDecoder {
             .ok_or_else(|| DecodingError::Format("IHDR chunk missing".into()))
     }
 
+    fn parse_unknown(&mut self, type_str: ChunkType) -> Result<Decoded, DecodingError> {
+        if !self.preserve_unknown_chunks || chunk::is_critical(type_str) {
+            return Ok(Decoded::PartialChunk(type_str));
+        }
+        let data = &self.current_chunk.raw_bytes;
+        if data.len() > self.limits.bytes {
+            return Err(DecodingError::LimitsExceeded);
+        }
+        self.limits.bytes -= data.len();
+        let info = self.info.as_mut().unwrap();
+        let position = if self.have_idat {
+            ChunkPosition::AfterIdat
+        } else if info.palette.is_some() {
+            ChunkPosition::BeforeIdat
+        } else {
+            ChunkPosition::BeforePlte
+        };
+        info.unknown_chunks.push(UnknownChunk {
+            chunk_type: type_str,
+            data: data.clone(),
+            position,
+        });
+        Ok(Decoded::PartialChunk(type_str))
+    }
+
     fn parse_fctl(&mut self) -> Result<Decoded, DecodingError> {
         let mut buf = &self.current_chunk.raw_bytes[..];
         let next_seq_no = buf.read_be()?;
@@ -671,6 +776,481 @@ impl StreamingDecoder {
         }
     }
 
+    fn parse_iccp(&mut self) -> Result<Decoded, DecodingError> {
+        if self.have_idat || self.info.as_ref().unwrap().palette.is_some() {
+            return Err(DecodingError::Format(
+                "iCCP chunk appeared after PLTE or IDAT chunk".into(),
+            ));
+        }
+        let buf = &self.current_chunk.raw_bytes[..];
+        let null_byte_index = buf.iter().position(|&b| b == 0).ok_or_else(|| {
+            DecodingError::Format("iCCP chunk is missing a null separator".into())
+        })?;
+        if null_byte_index == 0 || null_byte_index > 79 {
+            return Err(DecodingError::Format(
+                format!("invalid iCCP profile name length ({})", null_byte_index).into(),
+            ));
+        }
+        match buf.get(null_byte_index + 1) {
+            Some(0) => {}
+            Some(method) => {
+                return Err(DecodingError::Format(
+                    format!("unknown iCCP compression method ({})", method).into(),
+                ))
+            }
+            None => {
+                return Err(DecodingError::Format(
+                    "iCCP chunk is missing compression method".into(),
+                ))
+            }
+        }
+
+        let profile = decompress_zlib(&buf[null_byte_index + 2..], self.limits.bytes)?;
+        self.limits.bytes = self.limits.bytes.saturating_sub(profile.len());
+        self.info.as_mut().unwrap().icc_profile = Some(IccProfile {
+            name: decode_iso_8859_1(&buf[..null_byte_index]),
+            profile,
+        });
+        Ok(Decoded::Nothing)
+    }
+
+    fn parse_srgb(&mut self) -> Result<Decoded, DecodingError> {
+        if self.have_idat || self.info.as_ref().unwrap().palette.is_some() {
+            return Err(DecodingError::Format(
+                "sRGB chunk appeared after PLTE or IDAT chunk".into(),
+            ));
+        }
+        let mut buf = &self.current_chunk.raw_bytes[..];
+        let rendering_intent: u8 = buf.read_be()?;
+        let rendering_intent = SrgbRenderingIntent::from_u8(rendering_intent).ok_or_else(|| {
+            DecodingError::Format(
+                format!("invalid sRGB rendering intent ({})", rendering_intent).into(),
+            )
+        })?;
+        self.info.as_mut().unwrap().srgb = Some(rendering_intent);
+        Ok(Decoded::Nothing)
+    }
+
+    fn parse_sbit(&mut self) -> Result<Decoded, DecodingError> {
+        let info = self.info.as_mut().unwrap();
+        if self.have_idat || info.palette.is_some() {
+            return Err(DecodingError::Format(
+                "sBIT chunk appeared after PLTE or IDAT chunk".into(),
+            ));
+        }
+        let bits = &self.current_chunk.raw_bytes[..];
+        if !info.significant_bits_valid(bits) {
+            return Err(DecodingError::Format(
+                format!(
+                    "invalid sBIT chunk for color type ({})",
+                    info.color_type as u8
+                )
+                .into(),
+            ));
+        }
+        info.significant_bits = Some(bits.to_vec());
+        Ok(Decoded::Nothing)
+    }
+
+    fn parse_bkgd(&mut self) -> Result<Decoded, DecodingError> {
+        let info = self.info.as_mut().unwrap();
+        if self.have_idat {
+            return Err(DecodingError::Format(
+                "bKGD chunk appeared after IDAT chunk".into(),
+            ));
+        }
+        let color = BackgroundColor::from_bytes(info.color_type, &self.current_chunk.raw_bytes)
+            .filter(|&color| info.background_color_valid(color))
+            .ok_or_else(|| {
+                DecodingError::Format(
+                    format!(
+                        "invalid bKGD chunk for color type ({})",
+                        info.color_type as u8
+                    )
+                    .into(),
+                )
+            })?;
+        info.background_color = Some(color);
+        Ok(Decoded::Nothing)
+    }
+
+    fn parse_hist(&mut self) -> Result<Decoded, DecodingError> {
+        let info = self.info.as_mut().unwrap();
+        let entries = match &info.palette {
+            Some(palette) if !self.have_idat => palette.len() / 3,
+            _ => {
+                return Err(DecodingError::Format(
+                    "hIST chunk appeared before PLTE or after IDAT chunk".into(),
+                ))
+            }
+        };
+        let buf = &self.current_chunk.raw_bytes[..];
+        if buf.len() != 2 * entries {
+            return Err(DecodingError::Format(
+                format!(
+                    "hIST chunk length ({}) does not match the {} palette entries",
+                    buf.len(),
+                    entries
+                )
+                .into(),
+            ));
+        }
+        let histogram = buf
+            .chunks_exact(2)
+            .map(|frequency| u16::from_be_bytes([frequency[0], frequency[1]]))
+            .collect();
+        info.histogram = Some(histogram);
+        Ok(Decoded::Nothing)
+    }
+
+    fn parse_splt(&mut self) -> Result<Decoded, DecodingError> {
+        if self.have_idat {
+            return Err(DecodingError::Format(
+                "sPLT chunk appeared after IDAT chunk".into(),
+            ));
+        }
+        let buf = &self.current_chunk.raw_bytes[..];
+        let null_byte_index = buf.iter().position(|&b| b == 0).ok_or_else(|| {
+            DecodingError::Format("sPLT chunk is missing a null separator".into())
+        })?;
+        if null_byte_index == 0 || null_byte_index > 79 {
+            return Err(DecodingError::Format(
+                format!("invalid sPLT palette name length ({})", null_byte_index).into(),
+            ));
+        }
+        let name = decode_iso_8859_1(&buf[..null_byte_index]);
+        let (sample_depth, entry_len) = match buf.get(null_byte_index + 1) {
+            Some(8) => (BitDepth::Eight, 6),
+            Some(16) => (BitDepth::Sixteen, 10),
+            _ => return Err(DecodingError::Format("invalid sPLT sample depth".into())),
+        };
+        let data = buf[null_byte_index + 2..].chunks_exact(entry_len);
+        if !data.remainder().is_empty() {
+            return Err(DecodingError::Format(
+                format!("invalid sPLT chunk length ({})", buf.len()).into(),
+            ));
+        }
+        let entries = data
+            .map(|entry| {
+                let sample = |i: usize| match sample_depth {
+                    BitDepth::Eight => u16::from(entry[i]),
+                    _ => u16::from_be_bytes([entry[2 * i], entry[2 * i + 1]]),
+                };
+                let frequency = &entry[entry_len - 2..];
+                SuggestedPaletteEntry {
+                    red: sample(0),
+                    green: sample(1),
+                    blue: sample(2),
+                    alpha: sample(3),
+                    frequency: u16::from_be_bytes([frequency[0], frequency[1]]),
+                }
+            })
+            .collect();
+
+        let info = self.info.as_mut().unwrap();
+        if info
+            .suggested_palettes
+            .iter()
+            .any(|palette| palette.name == name)
+        {
+            return Err(DecodingError::Format(
+                format!("duplicate sPLT palette name ({})", name).into(),
+            ));
+        }
+        info.suggested_palettes.push(SuggestedPalette {
+            name,
+            sample_depth,
+            entries,
+        });
+        Ok(Decoded::Nothing)
+    }
+
+    fn parse_time(&mut self) -> Result<Decoded, DecodingError> {
+        let mut buf = &self.current_chunk.raw_bytes[..];
+        let year: u16 = buf.read_be()?;
+        let mut fields = [0; 5];
+        for field in fields.iter_mut() {
+            *field = buf.read_be()?;
+        }
+        let [month, day, hour, minute, second] = fields;
+        let time = Time::new(year, month, day, hour, minute, second).ok_or_else(|| {
+            DecodingError::Format(format!("invalid tIME chunk ({:?})", fields).into())
+        })?;
+        self.info.as_mut().unwrap().last_modified = Some(time);
+        Ok(Decoded::Nothing)
+    }
+
+    fn parse_exif(&mut self) -> Result<Decoded, DecodingError> {
+        let exif = self.current_chunk.raw_bytes.clone();
+        self.info.as_mut().unwrap().exif_metadata = Some(exif);
+        Ok(Decoded::Nothing)
+    }
+
+    fn parse_cicp(&mut self) -> Result<Decoded, DecodingError> {
+        if self.have_idat || self.info.as_ref().unwrap().palette.is_some() {
+            return Err(DecodingError::Format(
+                "cICP chunk appeared after PLTE or IDAT chunk".into(),
+            ));
+        }
+        let mut buf = &self.current_chunk.raw_bytes[..];
+        let color_primaries: u8 = buf.read_be()?;
+        let transfer_function: u8 = buf.read_be()?;
+        let matrix_coefficients: u8 = buf.read_be()?;
+        let full_range: u8 = buf.read_be()?;
+        if matrix_coefficients != 0 || full_range > 1 {
+            return Err(DecodingError::Format(
+                format!(
+                    "invalid cICP matrix coefficients ({}) or full range flag ({})",
+                    matrix_coefficients, full_range
+                )
+                .into(),
+            ));
+        }
+        self.info.as_mut().unwrap().coding_independent_code_points =
+            Some(CodingIndependentCodePoints {
+                color_primaries,
+                transfer_function,
+                matrix_coefficients,
+                is_video_full_range_image: full_range == 1,
+            });
+        Ok(Decoded::Nothing)
+    }
+
+    fn parse_mdcv(&mut self) -> Result<Decoded, DecodingError> {
+        if self.have_idat {
+            return Err(DecodingError::Format(
+                "mDCv chunk appeared after IDAT chunk".into(),
+            ));
+        }
+        let mut buf = &self.current_chunk.raw_bytes[..];
+        let mut chromaticities = [0u16; 8];
+        for value in chromaticities.iter_mut() {
+            *value = buf.read_be()?;
+        }
+        let max_luminance: u32 = buf.read_be()?;
+        let min_luminance: u32 = buf.read_be()?;
+        self.info.as_mut().unwrap().mastering_display_color_volume =
+            Some(MasteringDisplayColorVolume {
+                red: (chromaticities[0], chromaticities[1]),
+                green: (chromaticities[2], chromaticities[3]),
+                blue: (chromaticities[4], chromaticities[5]),
+                white: (chromaticities[6], chromaticities[7]),
+                max_luminance,
+                min_luminance,
+            });
+        Ok(Decoded::Nothing)
+    }
+
+    fn parse_clli(&mut self) -> Result<Decoded, DecodingError> {
+        if self.have_idat {
+            return Err(DecodingError::Format(
+                "cLLi chunk appeared after IDAT chunk".into(),
+            ));
+        }
+        let mut buf = &self.current_chunk.raw_bytes[..];
+        let max_content_light_level: u32 = buf.read_be()?;
+        let max_frame_average_light_level: u32 = buf.read_be()?;
+        self.info.as_mut().unwrap().content_light_level = Some(ContentLightLevelInfo {
+            max_content_light_level,
+            max_frame_average_light_level,
+        });
+        Ok(Decoded::Nothing)
+    }
+
+    fn parse_offs(&mut self) -> Result<Decoded, DecodingError> {
+        if self.have_idat {
+            return Err(DecodingError::Format(
+                "oFFs chunk appeared after IDAT chunk".into(),
+            ));
+        }
+        let mut buf = &self.current_chunk.raw_bytes[..];
+        let x = buf.read_be()?;
+        let y = buf.read_be()?;
+        let unit: u8 = buf.read_be()?;
+        let unit = OffsetUnit::from_u8(unit)
+            .ok_or_else(|| DecodingError::Format(format!("invalid oFFs unit ({})", unit).into()))?;
+        self.info.as_mut().unwrap().offset = Some(ImageOffset { x, y, unit });
+        Ok(Decoded::Nothing)
+    }
+
+    fn parse_pcal(&mut self) -> Result<Decoded, DecodingError> {
+        if self.have_idat {
+            return Err(DecodingError::Format(
+                "pCAL chunk appeared after IDAT chunk".into(),
+            ));
+        }
+        let buf = &self.current_chunk.raw_bytes[..];
+        let null_byte_index = buf.iter().position(|&b| b == 0).ok_or_else(|| {
+            DecodingError::Format("pCAL chunk is missing a null separator".into())
+        })?;
+        if null_byte_index == 0 || null_byte_index > 79 {
+            return Err(DecodingError::Format(
+                format!("invalid pCAL calibration name length ({})", null_byte_index).into(),
+            ));
+        }
+        let name = decode_iso_8859_1(&buf[..null_byte_index]);
+        let mut header = &buf[null_byte_index + 1..];
+        let original_zero = header.read_be()?;
+        let original_max = header.read_be()?;
+        let equation_type: u8 = header.read_be()?;
+        let parameter_count: u8 = header.read_be()?;
+        let equation_type = EquationType::from_u8(equation_type).ok_or_else(|| {
+            DecodingError::Format(format!("invalid pCAL equation type ({})", equation_type).into())
+        })?;
+        if original_zero == original_max
+            || usize::from(parameter_count) != equation_type.parameter_count()
+        {
+            return Err(DecodingError::Format("invalid pCAL chunk".into()));
+        }
+        // The unit and the parameters follow, each separated by a null byte.
+        let mut fields = header.split(|&b| b == 0);
+        let unit = decode_iso_8859_1(fields.next().unwrap_or(&[]));
+        let parameters = fields
+            .map(parse_ascii_float)
+            .collect::<Option<Vec<_>>>()
+            .filter(|parameters| parameters.len() == usize::from(parameter_count))
+            .ok_or_else(|| DecodingError::Format("invalid pCAL parameters".into()))?;
+        self.info.as_mut().unwrap().pixel_calibration = Some(PixelCalibration {
+            name,
+            original_zero,
+            original_max,
+            equation_type,
+            unit,
+            parameters,
+        });
+        Ok(Decoded::Nothing)
+    }
+
+    fn parse_scal(&mut self) -> Result<Decoded, DecodingError> {
+        if self.have_idat {
+            return Err(DecodingError::Format(
+                "sCAL chunk appeared after IDAT chunk".into(),
+            ));
+        }
+        let buf = &self.current_chunk.raw_bytes[..];
+        let unit = buf
+            .first()
+            .and_then(|&unit| ScaleUnit::from_u8(unit))
+            .ok_or_else(|| DecodingError::Format("invalid sCAL unit".into()))?;
+        let sizes = buf[1..]
+            .split(|&b| b == 0)
+            .map(|size| parse_ascii_float(size).filter(|&size| size > 0.0))
+            .collect::<Vec<_>>();
+        match sizes[..] {
+            [Some(pixel_width), Some(pixel_height)] => {
+                self.info.as_mut().unwrap().physical_scale = Some(PhysicalScale {
+                    unit,
+                    pixel_width,
+                    pixel_height,
+                });
+                Ok(Decoded::Nothing)
+            }
+            _ => Err(DecodingError::Format("invalid sCAL pixel size".into())),
+        }
+    }
+
+    fn parse_ster(&mut self) -> Result<Decoded, DecodingError> {
+        if self.have_idat {
+            return Err(DecodingError::Format(
+                "sTER chunk appeared after IDAT chunk".into(),
+            ));
+        }
+        let layout = match self.current_chunk.raw_bytes[..] {
+            [0] => StereoLayout::CrossFuse,
+            [1] => StereoLayout::DivergingFuse,
+            _ => return Err(DecodingError::Format("invalid sTER chunk".into())),
+        };
+        self.info.as_mut().unwrap().stereo_layout = Some(layout);
+        Ok(Decoded::Nothing)
+    }
+
+    fn parse_text(&mut self) -> Result<Decoded, DecodingError> {
+        let buf = &self.current_chunk.raw_bytes[..];
+        let null_byte_index = buf.iter().position(|&b| b == 0).ok_or_else(|| {
+            DecodingError::Format("tEXt chunk is missing a null separator".into())
+        })?;
+
+        let text = TEXtChunk::decode(&buf[..null_byte_index], &buf[null_byte_index + 1..])?;
+        self.info
+            .as_mut()
+            .unwrap()
+            .uncompressed_latin1_text
+            .push(text.clone());
+        Ok(Decoded::TEXtChunk(text))
+    }
+
+    fn parse_ztxt(&mut self) -> Result<Decoded, DecodingError> {
+        let buf = &self.current_chunk.raw_bytes[..];
+        let null_byte_index = buf.iter().position(|&b| b == 0).ok_or_else(|| {
+            DecodingError::Format("zTXt chunk is missing a null separator".into())
+        })?;
+        let compression_method = *buf.get(null_byte_index + 1).ok_or_else(|| {
+            DecodingError::Format("zTXt chunk is missing compression method".into())
+        })?;
+
+        let text = ZTXtChunk::decode(
+            &buf[..null_byte_index],
+            compression_method,
+            &buf[null_byte_index + 2..],
+            self.limits.bytes,
+        )?;
+        // Each inflated Latin-1 byte became one character of the text.
+        let inflated_len = text.text.chars().count();
+        self.limits.bytes = self.limits.bytes.saturating_sub(inflated_len);
+        self.info
+            .as_mut()
+            .unwrap()
+            .compressed_latin1_text
+            .push(text.clone());
+        Ok(Decoded::ZTXtChunk(text))
+    }
+
+    fn parse_itxt(&mut self) -> Result<Decoded, DecodingError> {
+        let buf = &self.current_chunk.raw_bytes[..];
+        let missing_separator =
+            || DecodingError::Format("iTXt chunk is missing a null separator".into());
+
+        let null_1 = buf
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or_else(missing_separator)?;
+        let (compression_flag, compression_method) = match buf.get(null_1 + 1..null_1 + 3) {
+            Some(flags) => (flags[0], flags[1]),
+            None => {
+                return Err(DecodingError::Format(
+                    "iTXt chunk is missing compression flags".into(),
+                ))
+            }
+        };
+        let language_start = null_1 + 3;
+        let null_2 = buf[language_start..]
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or_else(missing_separator)?
+            + language_start;
+        let null_3 = buf[null_2 + 1..]
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or_else(missing_separator)?
+            + null_2
+            + 1;
+
+        let text = ITXtChunk::decode(
+            &buf[..null_1],
+            compression_flag,
+            compression_method,
+            &buf[language_start..null_2],
+            &buf[null_2 + 1..null_3],
+            &buf[null_3 + 1..],
+            self.limits.bytes,
+        )?;
+        if text.compressed {
+            self.limits.bytes = self.limits.bytes.saturating_sub(text.text.len());
+        }
+        self.info.as_mut().unwrap().utf8_text.push(text.clone());
+        Ok(Decoded::ITXtChunk(text))
+    }
+
     fn parse_ihdr(&mut self) -> Result<Decoded, DecodingError> {
         // TODO: check if color/bit depths combination is valid
         let mut buf = &self.current_chunk.raw_bytes[..];
@@ -774,8 +1354,14 @@ pub fn get_info(d: &StreamingDecoder) -> Option<&Info> {
 
 #[cfg(test)]
 mod tests {
+    use super::BackgroundColor;
+    use super::BitDepth;
     use super::ScaledFloat;
     use super::SourceChromaticities;
+    use super::SrgbRenderingIntent;
+    use super::SuggestedPaletteEntry;
+    use super::Time;
+    use crate::text_metadata::{ITXtChunk, TEXtChunk, ZTXtChunk};
     use std::fs::File;
 
     #[test]
@@ -819,6 +1405,120 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn image_srgb() {
+        fn trial(path: &str, expected: Option<SrgbRenderingIntent>) {
+            let decoder = crate::Decoder::new(File::open(path).unwrap());
+            let (_, reader) = decoder.read_info().unwrap();
+            assert_eq!(reader.info().srgb, expected);
+        }
+        trial(
+            "tests/benches/kodim02.png",
+            Some(SrgbRenderingIntent::Perceptual),
+        );
+        trial(
+            "tests/benches/Transparency.png",
+            Some(SrgbRenderingIntent::Perceptual),
+        );
+        trial("tests/pngsuite/basn2c08.png", None);
+        trial("tests/pngsuite/ccwn2c08.png", None);
+    }
+
+    #[test]
+    fn image_significant_bits() {
+        fn trial(path: &str, expected: Option<&[u8]>) {
+            let decoder = crate::Decoder::new(File::open(path).unwrap());
+            let (_, reader) = decoder.read_info().unwrap();
+            assert_eq!(reader.info().significant_bits.as_deref(), expected);
+        }
+        trial("tests/pngsuite/cs3n2c16.png", Some(&[13, 13, 13]));
+        trial("tests/pngsuite/cs5n2c08.png", Some(&[5, 5, 5]));
+        trial("tests/pngsuite/cs3n3p08.png", Some(&[3, 3, 3]));
+        trial("tests/pngsuite/cs8n2c08.png", None);
+        trial("tests/pngsuite/basn2c08.png", None);
+    }
+
+    #[test]
+    fn image_background_color() {
+        fn trial(path: &str, expected: Option<BackgroundColor>) {
+            let decoder = crate::Decoder::new(File::open(path).unwrap());
+            let (_, reader) = decoder.read_info().unwrap();
+            assert_eq!(reader.info().background_color, expected);
+        }
+        trial(
+            "tests/pngsuite/bggn4a16.png",
+            Some(BackgroundColor::Gray(0xAB84)),
+        );
+        trial(
+            "tests/pngsuite/bgyn6a16.png",
+            Some(BackgroundColor::Rgb(0xFFFF, 0xFFFF, 0)),
+        );
+        trial(
+            "tests/pngsuite/tbbn3p08.png",
+            Some(BackgroundColor::Indexed(0xF5)),
+        );
+        trial("tests/pngsuite/bgai4a08.png", None);
+    }
+
+    #[test]
+    fn image_palette_auxiliary() {
+        let info = |path: &str| {
+            let decoder = crate::Decoder::new(File::open(path).unwrap());
+            let (_, reader) = decoder.read_info().unwrap();
+            reader.info().clone()
+        };
+        let histogram = info("tests/pngsuite/ch1n3p04.png").histogram.unwrap();
+        assert_eq!(histogram.len(), 15);
+        assert_eq!(histogram[..3], [64, 112, 48]);
+        assert!(info("tests/pngsuite/basn3p04.png").histogram.is_none());
+
+        for &(path, depth) in [
+            ("tests/pngsuite/ps1n0g08.png", BitDepth::Eight),
+            ("tests/pngsuite/ps2n0g08.png", BitDepth::Sixteen),
+        ]
+        .iter()
+        {
+            let palettes = info(path).suggested_palettes;
+            assert_eq!(palettes.len(), 1);
+            assert_eq!(palettes[0].name, "six-cube");
+            assert_eq!(palettes[0].sample_depth, depth);
+            assert_eq!(palettes[0].entries.len(), 216);
+        }
+        let palettes = info("tests/pngsuite/ps1n0g08.png").suggested_palettes;
+        assert_eq!(
+            palettes[0].entries[1],
+            SuggestedPaletteEntry {
+                red: 0,
+                green: 0,
+                blue: 0x33,
+                alpha: 0xFF,
+                frequency: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn image_last_modified() {
+        fn trial(path: &str, expected: Option<Time>) {
+            let decoder = crate::Decoder::new(File::open(path).unwrap());
+            let (_, reader) = decoder.read_info().unwrap();
+            assert_eq!(reader.info().last_modified, expected);
+        }
+        trial(
+            "tests/pngsuite/cm0n0g04.png",
+            Time::new(2000, 1, 1, 12, 34, 56),
+        );
+        trial(
+            "tests/pngsuite/cm7n0g04.png",
+            Time::new(1970, 1, 1, 0, 0, 0),
+        );
+        trial(
+            "tests/pngsuite/cm9n0g04.png",
+            Time::new(1999, 12, 31, 23, 59, 59),
+        );
+        trial("tests/pngsuite/basn0g04.png", None);
+    }
+
     #[test]
     fn image_source_chromaticities() -> Result<(), ()> {
         fn trial(path: &str, expected: Option<SourceChromaticities>) {
@@ -1005,4 +1705,84 @@ mod tests {
         trial("tests/pngsuite/z06n2c08.png", None);
         Ok(())
     }
+
+    #[test]
+    fn image_text_chunks() {
+        let decoder = crate::Decoder::new(File::open("tests/pngsuite/ctzn0g04.png").unwrap());
+        let (_, mut reader) = decoder.read_info().unwrap();
+        reader.finish().unwrap();
+        let info = reader.info();
+        assert_eq!(
+            info.uncompressed_latin1_text[0],
+            TEXtChunk::new("Title", "PngSuite")
+        );
+        assert_eq!(info.uncompressed_latin1_text.len(), 2);
+        assert_eq!(
+            info.compressed_latin1_text[0],
+            ZTXtChunk::new(
+                "Copyright",
+                "Copyright Willem van Schaik, Singapore 1995-96"
+            )
+        );
+        assert_eq!(info.compressed_latin1_text.len(), 4);
+        assert!(info.utf8_text.is_empty());
+
+        let decoder = crate::Decoder::new(File::open("tests/pngsuite/ctgn0g04.png").unwrap());
+        let (_, reader) = decoder.read_info().unwrap();
+        let info = reader.info();
+        let mut expected = ITXtChunk::new("Disclaimer", "Δωρεάν λογισμικό.");
+        expected.language_tag = "el".to_string();
+        expected.translated_keyword = "Αποποίηση".to_string();
+        assert_eq!(info.utf8_text.len(), 6);
+        assert_eq!(info.utf8_text[5], expected);
+    }
+
+    #[test]
+    fn text_chunk_limits() {
+        let limits = crate::Limits { bytes: 16 * 1024 };
+        let decoder = crate::Decoder::new_with_limits(
+            File::open("tests/pngsuite/ctzn0g04.png").unwrap(),
+            limits,
+        );
+        let (_, mut reader) = decoder.read_info().unwrap();
+        assert!(reader.finish().is_ok());
+
+        let limits = crate::Limits { bytes: 100 };
+        let decoder = crate::Decoder::new_with_limits(
+            File::open("tests/pngsuite/ctzn0g04.png").unwrap(),
+            limits,
+        );
+        match decoder.read_info() {
+            Err(crate::DecodingError::LimitsExceeded) => {}
+            Err(err) => panic!("unexpected error {}", err),
+            Ok(_) => panic!("zTXt chunks exceeding the limit were decoded"),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "png-encoding")]
+    fn large_text_chunk() {
+        let text = "<x:xmpmeta/>".repeat(100_000 / 12);
+        let mut data = b"XML:com.adobe.xmp\0\0\0\0\0".to_vec();
+        data.extend_from_slice(text.as_bytes());
+        let mut png = Vec::new();
+        {
+            let mut encoder = crate::Encoder::new(&mut png, 1, 1);
+            encoder.set_color(crate::ColorType::Grayscale);
+            encoder.set_depth(crate::BitDepth::Eight);
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_chunk(crate::chunk::iTXt, &data).unwrap();
+            writer.write_image_data(&[0]).unwrap();
+        }
+
+        let (_, reader) = crate::Decoder::new(&png[..]).read_info().unwrap();
+        let info = reader.info();
+        assert_eq!(info.utf8_text.len(), 1);
+        assert_eq!(info.utf8_text[0].keyword, "XML:com.adobe.xmp");
+        assert_eq!(info.utf8_text[0].text, text);
+
+        let limits = crate::Limits { bytes: 64 * 1024 };
+        let decoder = crate::Decoder::new_with_limits(&png[..], limits);
+        assert!(decoder.read_info().is_err());
+    }
 }
@@ -0,0 +1,1037 @@
+//! Row and frame transformations applied by the `Reader` according to its `Transformations`.
+use super::DecodingError;
+use crate::common::{BackgroundColor, BitDepth, ColorType, Info, Orientation, Transformations};
+use crate::utils;
+
+/// Whether the image is expanded, which compositing onto a background requires for tRNS.
+fn expands(info: &Info, t: Transformations) -> bool {
+    t.intersects(Transformations::EXPAND | Transformations::EXPAND_16)
+        || (t.contains(Transformations::BACKGROUND) && info.trns.is_some())
+}
+
+/// The color type and bit depth after expansion, the first transformation.
+fn expanded_layout(info: &Info, t: Transformations) -> (ColorType, BitDepth) {
+    use crate::common::ColorType::*;
+    let expand = expands(info, t);
+    let trns = info.trns.is_some();
+    let depth = info.bit_depth;
+    let sub_byte = (depth as u8) < 8;
+    match info.color_type {
+        Indexed if expand => (if trns { RGBA } else { RGB }, BitDepth::Eight),
+        Grayscale if expand && trns && sub_byte => (GrayscaleAlpha, BitDepth::Eight),
+        Grayscale if expand && trns => (GrayscaleAlpha, depth),
+        RGB if expand && trns => (RGBA, depth),
+        Grayscale if sub_byte && (expand || t.contains(Transformations::GRAY_TO_RGB)) => {
+            (Grayscale, BitDepth::Eight)
+        }
+        color if sub_byte && t.contains(Transformations::PACKING) => (color, BitDepth::Eight),
+        color => (color, depth),
+    }
+}
+
+/// The color type and bit depth of rows output with the transformations.
+///
+/// Reordering transformations such as `BGR` and `SWAP_ALPHA` do not change the reported color
+/// type, only the order of the samples within each pixel.
+pub(super) fn output_layout(info: &Info, t: Transformations) -> (ColorType, BitDepth) {
+    use crate::common::ColorType::*;
+    let (mut color, mut depth) = expanded_layout(info, t);
+    if t.intersects(Transformations::STRIP_ALPHA | Transformations::BACKGROUND) {
+        color = match color {
+            GrayscaleAlpha => Grayscale,
+            RGBA => RGB,
+            color => color,
+        };
+    }
+    if depth == BitDepth::Sixteen
+        && t.intersects(Transformations::STRIP_16 | Transformations::SCALE_16)
+    {
+        depth = BitDepth::Eight;
+    }
+    if t.contains(Transformations::GRAY_TO_RGB) {
+        color = match color {
+            Grayscale => RGB,
+            GrayscaleAlpha => RGBA,
+            color => color,
+        };
+    }
+    if depth == BitDepth::Eight && color != Indexed && t.contains(Transformations::EXPAND_16) {
+        depth = BitDepth::Sixteen;
+    }
+    (color, depth)
+}
+
+/// The number of bytes required to transform a row of `width` pixels.
+///
+/// Rows only shrink after the expansion, so this is the larger of the expanded and output rows.
+pub(super) fn buffer_line_size(info: &Info, t: Transformations, width: u32) -> Option<usize> {
+    let (color, depth) = expanded_layout(info, t);
+    let expanded = color.checked_raw_row_length(depth, width)?;
+    let (color, depth) = output_layout(info, t);
+    let output = color.checked_raw_row_length(depth, width)?;
+    // Without the filter method byte
+    Some(expanded.max(output) - 1)
+}
+
+/// Lookup tables correcting samples from the gamma of the image to that of the screen.
+pub(super) struct GammaCorrection {
+    table8: Vec<u8>,
+    /// Only computed for 16-bit images.
+    table16: Vec<u16>,
+    /// The corrected palette of an indexed image.
+    palette: Option<Vec<u8>>,
+}
+
+impl GammaCorrection {
+    /// The gamma assumed for images without a gAMA chunk, that of sRGB.
+    const DEFAULT_FILE_GAMMA: f64 = 1.0 / 2.2;
+
+    /// Create the tables for displaying the image on a screen with gamma `screen_gamma`.
+    pub(super) fn new(info: &Info, screen_gamma: f64) -> GammaCorrection {
+        let file_gamma = match info.source_gamma {
+            Some(gamma) if gamma.into_scaled() > 0 => f64::from(gamma.into_scaled()) / 100_000.0,
+            _ => Self::DEFAULT_FILE_GAMMA,
+        };
+        let exponent = 1.0 / (file_gamma * screen_gamma);
+        let correct = |sample: f64, max: f64| ((sample / max).powf(exponent) * max).round();
+
+        let table8: Vec<u8> = (0..=255)
+            .map(|sample| correct(f64::from(sample), 255.0) as u8)
+            .collect();
+        let table16 = if info.bit_depth == BitDepth::Sixteen {
+            (0..=0xFFFF)
+                .map(|sample| correct(f64::from(sample), 65535.0) as u16)
+                .collect()
+        } else {
+            Vec::new()
+        };
+        let palette = info.palette.as_ref().map(|palette| {
+            palette
+                .iter()
+                .map(|&sample| table8[usize::from(sample)])
+                .collect()
+        });
+        GammaCorrection {
+            table8,
+            table16,
+            palette,
+        }
+    }
+
+    /// Correct the color samples of a row of 8 or 16 bit samples, leaving alpha unchanged.
+    fn correct_row(&self, row: &mut [u8], color: ColorType, depth: u8) {
+        let channels = color.samples();
+        let color_samples = match color {
+            ColorType::GrayscaleAlpha | ColorType::RGBA => channels - 1,
+            _ => channels,
+        };
+        let bps = usize::from(depth / 8);
+        for pixel in row.chunks_exact_mut(channels * bps) {
+            for sample in pixel[..color_samples * bps].chunks_exact_mut(bps) {
+                if depth == 16 {
+                    let value = u16::from_be_bytes([sample[0], sample[1]]);
+                    sample.copy_from_slice(&self.table16[usize::from(value)].to_be_bytes());
+                } else {
+                    sample[0] = self.table8[usize::from(sample[0])];
+                }
+            }
+        }
+    }
+}
+
+/// The background color pixels are composited onto with `Transformations::BACKGROUND`.
+pub(super) struct Background {
+    /// The color samples in the expanded color type and bit depth, big endian.
+    samples: Vec<u8>,
+}
+
+impl Background {
+    /// The background of the image, or `rgb` if given, for images with alpha after expansion.
+    ///
+    /// The 16-bit samples of `rgb` are scaled to the bit depth of the image, grayscale images use
+    /// its luminance. Indexed images are expanded from the palette corrected with `gamma`, so
+    /// their background is corrected as well.
+    pub(super) fn new(
+        info: &Info,
+        t: Transformations,
+        rgb: Option<(u16, u16, u16)>,
+        gamma: Option<&GammaCorrection>,
+    ) -> Option<Background> {
+        use crate::common::ColorType::*;
+        let (color, depth) = expanded_layout(info, t);
+        let channels = match color {
+            GrayscaleAlpha => 1,
+            RGBA => 3,
+            _ => return None,
+        };
+        let values = match (rgb, info.background_color) {
+            (Some((r, g, b)), _) => {
+                let values = if channels == 1 {
+                    vec![luminance(r, g, b)]
+                } else {
+                    vec![r, g, b]
+                };
+                match depth {
+                    BitDepth::Sixteen => values,
+                    _ => values.iter().map(|&v| u16::from(scale_16(v))).collect(),
+                }
+            }
+            (None, Some(BackgroundColor::Indexed(index))) => {
+                let start = 3 * usize::from(index);
+                let entry = info.palette.as_ref()?.get(start..start + 3)?;
+                entry.iter().cloned().map(u16::from).collect()
+            }
+            // Sub-byte samples are expanded to 8 bits.
+            (None, Some(BackgroundColor::Gray(gray))) if (info.bit_depth as u8) < 8 => {
+                vec![gray * (0xFF / ((1 << info.bit_depth as u8) - 1))]
+            }
+            (None, Some(BackgroundColor::Gray(gray))) => vec![gray],
+            (None, Some(BackgroundColor::Rgb(r, g, b))) => vec![r, g, b],
+            (None, None) => vec![0; channels],
+        };
+        let samples: Vec<u8> = match depth {
+            BitDepth::Sixteen => values
+                .iter()
+                .flat_map(|v| v.to_be_bytes().to_vec())
+                .collect(),
+            _ => values.iter().map(|&v| v as u8).collect(),
+        };
+        let samples = match gamma {
+            Some(gamma) if info.color_type == Indexed => samples
+                .iter()
+                .map(|&sample| gamma.table8[usize::from(sample)])
+                .collect(),
+            _ => samples,
+        };
+        Some(Background { samples })
+    }
+
+    /// Composite the pixels of a row with alpha onto the background, removing the alpha channel.
+    fn composite_row(&self, buf: &mut [u8], pixels: usize, depth: u8) {
+        let bps = usize::from(depth / 8);
+        let kept = self.samples.len();
+        let pixel_len = kept + bps;
+        let max: u32 = if depth == 16 { 0xFFFF } else { 0xFF };
+        let sample = |bytes: &[u8]| {
+            if depth == 16 {
+                u32::from(u16::from_be_bytes([bytes[0], bytes[1]]))
+            } else {
+                u32::from(bytes[0])
+            }
+        };
+        let background: Vec<u32> = self.samples.chunks_exact(bps).map(sample).collect();
+        for i in 0..pixels {
+            let (src, dst) = (i * pixel_len, i * kept);
+            let alpha = sample(&buf[src + kept..]);
+            for (c, &back) in background.iter().enumerate() {
+                let fore = sample(&buf[src + c * bps..]);
+                let value = (fore * alpha + back * (max - alpha) + max / 2) / max;
+                if depth == 16 {
+                    buf[dst + c * bps..dst + (c + 1) * bps]
+                        .copy_from_slice(&(value as u16).to_be_bytes());
+                } else {
+                    buf[dst + c] = value as u8;
+                }
+            }
+        }
+    }
+}
+
+/// The luminance of a color, with the coefficients of ITU-R BT.709 used by libpng.
+fn luminance(r: u16, g: u16, b: u16) -> u16 {
+    let weighted = 6968 * u32::from(r) + 23434 * u32::from(g) + 2366 * u32::from(b);
+    ((weighted + 16384) >> 15) as u16
+}
+
+/// Transform the raw row of `width` pixels at the start of `buf` in place.
+///
+/// The buffer must be at least `buffer_line_size` long. Returns the length of the transformed row.
+pub(super) fn transform_row(
+    buf: &mut [u8],
+    width: u32,
+    info: &Info,
+    t: Transformations,
+    gamma: Option<&GammaCorrection>,
+    background: Option<&Background>,
+) -> Result<usize, DecodingError> {
+    use crate::common::ColorType::*;
+    let pixels = width as usize;
+    let (mut color, depth) = expanded_layout(info, t);
+    let mut depth = depth as u8;
+    let row_len = |color: ColorType, depth: u8| {
+        color.raw_row_length_from_width(BitDepth::from_u8(depth).unwrap(), width) - 1
+    };
+
+    let expanded_len = row_len(color, depth);
+    // Whether samples of less than 8 bits are scaled to the full range when unpacked.
+    let rescale = expands(info, t) || t.contains(Transformations::GRAY_TO_RGB);
+    if (color, depth) != (info.color_type, info.bit_depth as u8) {
+        let buf = &mut buf[..expanded_len];
+        match info.color_type {
+            Indexed if color != Indexed => {
+                let palette = gamma.and_then(|gamma| gamma.palette.as_ref());
+                expand_paletted(buf, info, palette.or(info.palette.as_ref()))?
+            }
+            Indexed => utils::unpack_bits(buf, 1, info.bit_depth as u8, |i, chunk| chunk[0] = i),
+            Grayscale | RGB if info.bit_depth as u8 >= 8 => {
+                let channels = info.color_type.samples();
+                let trns = info.trns.as_ref().unwrap();
+                if depth == 8 {
+                    utils::expand_trns_line(buf, trns, channels);
+                } else {
+                    utils::expand_trns_line16(buf, trns, channels);
+                }
+            }
+            _ => {
+                let trns = if color == GrayscaleAlpha {
+                    info.trns.as_ref().map(|trns| trns[0])
+                } else {
+                    None
+                };
+                expand_gray_u8(buf, info.bit_depth as u8, rescale, trns)
+            }
+        }
+    }
+    let mut len = expanded_len;
+
+    let bytes_per_sample = |depth: u8| usize::from(depth / 8);
+    let has_alpha = color == GrayscaleAlpha || color == RGBA;
+    if let (Some(background), true) = (
+        background,
+        has_alpha && t.contains(Transformations::BACKGROUND),
+    ) {
+        background.composite_row(buf, pixels, depth);
+        color = if color == RGBA { RGB } else { Grayscale };
+        len = row_len(color, depth);
+    }
+
+    if t.intersects(Transformations::STRIP_ALPHA | Transformations::BACKGROUND)
+        && (color == GrayscaleAlpha || color == RGBA)
+    {
+        let bps = bytes_per_sample(depth);
+        let channels = color.samples();
+        let pixel_len = channels * bps;
+        let kept = pixel_len - bps;
+        for i in 0..pixels {
+            for b in 0..kept {
+                buf[i * kept + b] = buf[i * pixel_len + b];
+            }
+        }
+        color = if color == RGBA { RGB } else { Grayscale };
+        len = row_len(color, depth);
+    }
+
+    // Paletted images are corrected through their palette, samples of less than 8 bits only when
+    // they are scaled to the full range.
+    if let Some(gamma) = gamma {
+        if depth >= 8 && (info.bit_depth as u8 >= 8 || rescale) && info.color_type != Indexed {
+            gamma.correct_row(&mut buf[..len], color, depth);
+        }
+    }
+
+    if depth == 16 && t.contains(Transformations::SCALE_16) {
+        len /= 2;
+        for i in 0..len {
+            buf[i] = scale_16(u16::from_be_bytes([buf[2 * i], buf[2 * i + 1]]));
+        }
+        depth = 8;
+    } else if depth == 16 && t.contains(Transformations::STRIP_16) {
+        len /= 2;
+        for i in 0..len {
+            buf[i] = buf[2 * i];
+        }
+        depth = 8;
+    }
+
+    if t.contains(Transformations::INVERT_MONO) {
+        match color {
+            Grayscale => buf[..len].iter_mut().for_each(|b| *b = !*b),
+            GrayscaleAlpha => {
+                let bps = bytes_per_sample(depth);
+                for pixel in buf[..len].chunks_exact_mut(2 * bps) {
+                    pixel[..bps].iter_mut().for_each(|b| *b = !*b);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if t.contains(Transformations::GRAY_TO_RGB) && (color == Grayscale || color == GrayscaleAlpha) {
+        let bps = bytes_per_sample(depth);
+        let alpha = color == GrayscaleAlpha;
+        let (from, to) = if alpha {
+            (2 * bps, 4 * bps)
+        } else {
+            (bps, 3 * bps)
+        };
+        for i in (0..pixels).rev() {
+            let (src, dst) = (i * from, i * to);
+            if alpha {
+                for b in 0..bps {
+                    buf[dst + 3 * bps + b] = buf[src + bps + b];
+                }
+            }
+            for c in (0..3).rev() {
+                for b in 0..bps {
+                    buf[dst + c * bps + b] = buf[src + b];
+                }
+            }
+        }
+        color = if alpha { RGBA } else { RGB };
+        len = row_len(color, depth);
+    }
+
+    if t.contains(Transformations::EXPAND_16) && depth == 8 && color != Indexed {
+        for i in (0..len).rev() {
+            buf[2 * i] = buf[i];
+            buf[2 * i + 1] = buf[i];
+        }
+        len *= 2;
+        depth = 16;
+    }
+
+    if let (true, Some(bits)) = (
+        t.contains(Transformations::SHIFT),
+        info.significant_bits.as_ref(),
+    ) {
+        // Unpacked samples that are not rescaled keep their original range.
+        let sample_depth = if (info.bit_depth as u8) < 8 && !rescale {
+            info.bit_depth as u8
+        } else {
+            depth
+        };
+        shift_row(
+            &mut buf[..len],
+            color,
+            depth,
+            sample_depth,
+            info.color_type,
+            bits,
+        );
+    }
+
+    if t.contains(Transformations::PACKSWAP) && depth < 8 {
+        let per_byte = 8 / depth;
+        let mask = (1u8 << depth) - 1;
+        for byte in buf[..len].iter_mut() {
+            let mut swapped = 0;
+            for i in 0..per_byte {
+                let value = (*byte >> (i * depth)) & mask;
+                swapped |= value << ((per_byte - 1 - i) * depth);
+            }
+            *byte = swapped;
+        }
+    }
+
+    if color == GrayscaleAlpha || color == RGBA {
+        let bps = bytes_per_sample(depth);
+        let pixel_len = color.samples() * bps;
+        let alpha = pixel_len - bps;
+        for pixel in buf[..len].chunks_exact_mut(pixel_len) {
+            if t.contains(Transformations::INVERT_ALPHA) {
+                pixel[alpha..].iter_mut().for_each(|b| *b = !*b);
+            }
+            if t.contains(Transformations::BGR) && color == RGBA {
+                swap_samples(pixel, 0, 2, bps);
+            }
+            if t.contains(Transformations::SWAP_ALPHA) {
+                pixel.rotate_right(bps);
+            }
+        }
+    } else if t.contains(Transformations::BGR) && color == RGB {
+        let bps = bytes_per_sample(depth);
+        for pixel in buf[..len].chunks_exact_mut(3 * bps) {
+            swap_samples(pixel, 0, 2, bps);
+        }
+    }
+
+    if t.contains(Transformations::SWAP_ENDIAN) && depth == 16 {
+        for sample in buf[..len].chunks_exact_mut(2) {
+            sample.swap(0, 1);
+        }
+    }
+
+    Ok(len)
+}
+
+/// Copy the `width` by `height` pixels of `bits` bits each of the stored frame into `out`, rotated
+/// and flipped for display in `orientation`.
+///
+/// Pixels of less than 8 bits are packed with the first pixel in the high bits, or in the low bits
+/// with `lsb_first`.
+pub(super) fn orient_frame(
+    stored: &[u8],
+    out: &mut [u8],
+    (width, height): (u32, u32),
+    bits: usize,
+    lsb_first: bool,
+    orientation: Orientation,
+) {
+    use crate::common::Orientation::*;
+    let (w, h) = (width as usize, height as usize);
+    let (out_w, out_h) = if orientation.transposes() {
+        (h, w)
+    } else {
+        (w, h)
+    };
+    let stored_row = (w * bits + 7) >> 3;
+    let out_row = (out_w * bits + 7) >> 3;
+    let mask = if bits < 8 { (1u8 << bits) - 1 } else { 0xFF };
+    let shift = |x: usize| {
+        let offset = x * bits % 8;
+        if lsb_first {
+            offset
+        } else {
+            8 - bits - offset
+        }
+    };
+    if bits < 8 {
+        out[..out_row * out_h].iter_mut().for_each(|b| *b = 0);
+    }
+    for y in 0..out_h {
+        for x in 0..out_w {
+            let (sx, sy) = match orientation {
+                TopLeft => (x, y),
+                TopRight => (w - 1 - x, y),
+                BottomRight => (w - 1 - x, h - 1 - y),
+                BottomLeft => (x, h - 1 - y),
+                LeftTop => (y, x),
+                RightTop => (y, h - 1 - x),
+                RightBottom => (w - 1 - y, h - 1 - x),
+                LeftBottom => (w - 1 - y, x),
+            };
+            let (src, dst) = (sy * stored_row, y * out_row);
+            if bits >= 8 {
+                let bytes = bits / 8;
+                out[dst + x * bytes..dst + (x + 1) * bytes]
+                    .copy_from_slice(&stored[src + sx * bytes..src + (sx + 1) * bytes]);
+            } else {
+                let value = (stored[src + sx * bits / 8] >> shift(sx)) & mask;
+                out[dst + x * bits / 8] |= value << shift(x);
+            }
+        }
+    }
+}
+
+/// Scale a 16-bit sample to 8 bits, rounding to the nearest value.
+fn scale_16(sample: u16) -> u8 {
+    ((u32::from(sample) * 255 + 32895) >> 16) as u8
+}
+
+/// Shift the samples of a row right to the number of significant bits of their channel.
+///
+/// `bits` are the significant bits of the channels of `original` color type while the row has
+/// the `color` type, with `sample_depth` bits of range in samples of `depth` bits.
+fn shift_row(
+    row: &mut [u8],
+    color: ColorType,
+    depth: u8,
+    sample_depth: u8,
+    original: ColorType,
+    bits: &[u8],
+) {
+    use crate::common::ColorType::*;
+    let (rgb, alpha) = match original {
+        Grayscale => ([bits[0]; 3], None),
+        GrayscaleAlpha => ([bits[0]; 3], Some(bits[1])),
+        RGB | Indexed => ([bits[0], bits[1], bits[2]], None),
+        RGBA => ([bits[0], bits[1], bits[2]], Some(bits[3])),
+    };
+    // An alpha channel expanded from tRNS uses its full range.
+    let alpha = alpha.unwrap_or(sample_depth);
+    let channel_bits = match color {
+        Grayscale => vec![rgb[0]],
+        GrayscaleAlpha => vec![rgb[0], alpha],
+        RGB => rgb.to_vec(),
+        RGBA => vec![rgb[0], rgb[1], rgb[2], alpha],
+        Indexed => return,
+    };
+    let shifts: Vec<u8> = channel_bits
+        .iter()
+        .map(|&bits| sample_depth.saturating_sub(bits))
+        .collect();
+    if shifts.iter().all(|&shift| shift == 0) {
+        return;
+    }
+
+    match depth {
+        16 => {
+            for pixel in row.chunks_exact_mut(2 * shifts.len()) {
+                for (sample, &shift) in pixel.chunks_exact_mut(2).zip(shifts.iter()) {
+                    let value = u16::from_be_bytes([sample[0], sample[1]]) >> shift;
+                    sample.copy_from_slice(&value.to_be_bytes());
+                }
+            }
+        }
+        8 => {
+            for pixel in row.chunks_exact_mut(shifts.len()) {
+                for (sample, &shift) in pixel.iter_mut().zip(shifts.iter()) {
+                    *sample >>= shift;
+                }
+            }
+        }
+        _ => {
+            // Packed grayscale samples.
+            let mask = (1u8 << depth) - 1;
+            for byte in row.iter_mut() {
+                let mut shifted = 0;
+                for i in (0..8).step_by(usize::from(depth)) {
+                    shifted |= ((*byte >> i & mask) >> shifts[0]) << i;
+                }
+                *byte = shifted;
+            }
+        }
+    }
+}
+
+fn swap_samples(pixel: &mut [u8], a: usize, b: usize, bytes_per_sample: usize) {
+    for i in 0..bytes_per_sample {
+        pixel.swap(a * bytes_per_sample + i, b * bytes_per_sample + i);
+    }
+}
+
+fn expand_paletted(
+    buffer: &mut [u8],
+    info: &Info,
+    palette: Option<&Vec<u8>>,
+) -> Result<(), DecodingError> {
+    if let Some(palette) = palette {
+        if let BitDepth::Sixteen = info.bit_depth {
+            Err(DecodingError::Format(
+                "Bit depth '16' is not valid for paletted images".into(),
+            ))
+        } else {
+            let black = [0, 0, 0];
+            if let Some(ref trns) = info.trns {
+                utils::unpack_bits(buffer, 4, info.bit_depth as u8, |i, chunk| {
+                    let (rgb, a) = (
+                        palette
+                            .get(3 * i as usize..3 * i as usize + 3)
+                            .unwrap_or(&black),
+                        *trns.get(i as usize).unwrap_or(&0xFF),
+                    );
+                    chunk[0] = rgb[0];
+                    chunk[1] = rgb[1];
+                    chunk[2] = rgb[2];
+                    chunk[3] = a;
+                });
+            } else {
+                utils::unpack_bits(buffer, 3, info.bit_depth as u8, |i, chunk| {
+                    let rgb = palette
+                        .get(3 * i as usize..3 * i as usize + 3)
+                        .unwrap_or(&black);
+                    chunk[0] = rgb[0];
+                    chunk[1] = rgb[1];
+                    chunk[2] = rgb[2];
+                })
+            }
+            Ok(())
+        }
+    } else {
+        Err(DecodingError::Format("missing palette".into()))
+    }
+}
+
+fn expand_gray_u8(buffer: &mut [u8], bit_depth: u8, rescale: bool, trns: Option<u8>) {
+    let scaling_factor = if rescale {
+        (255) / ((1u16 << bit_depth) - 1) as u8
+    } else {
+        1
+    };
+    if let Some(trns) = trns {
+        utils::unpack_bits(buffer, 2, bit_depth, |pixel, chunk| {
+            if pixel == trns {
+                chunk[1] = 0
+            } else {
+                chunk[1] = 0xFF
+            }
+            chunk[0] = pixel * scaling_factor
+        })
+    } else {
+        utils::unpack_bits(buffer, 1, bit_depth, |val, chunk| {
+            chunk[0] = val * scaling_factor
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(color_type: ColorType, bit_depth: BitDepth, trns: Option<Vec<u8>>) -> Info {
+        Info {
+            width: 2,
+            height: 1,
+            color_type,
+            bit_depth,
+            palette: Some((0..48).collect()),
+            trns,
+            ..Info::default()
+        }
+    }
+
+    fn transformed(info: &Info, t: Transformations, row: &[u8]) -> Vec<u8> {
+        let mut buf = vec![0; buffer_line_size(info, t, info.width).unwrap()];
+        buf[..row.len()].copy_from_slice(row);
+        let background = Background::new(info, t, None, None);
+        let len = transform_row(&mut buf, info.width, info, t, None, background.as_ref()).unwrap();
+        buf.truncate(len);
+        buf
+    }
+
+    #[test]
+    fn packing() {
+        let gray = info(ColorType::Grayscale, BitDepth::Two, None);
+        assert_eq!(
+            transformed(&gray, Transformations::PACKING, &[0b0111_0000]),
+            [1, 3]
+        );
+        assert_eq!(
+            transformed(&gray, Transformations::EXPAND, &[0b0111_0000]),
+            [0x55, 0xFF]
+        );
+        assert_eq!(
+            transformed(&gray, Transformations::PACKSWAP, &[0b0111_0000]),
+            [0b0000_1101]
+        );
+        let indexed = info(ColorType::Indexed, BitDepth::Four, None);
+        assert_eq!(
+            transformed(&indexed, Transformations::PACKING, &[0x2F]),
+            [2, 15]
+        );
+    }
+
+    #[test]
+    fn sixteen_bit() {
+        let rgba = info(ColorType::RGBA, BitDepth::Sixteen, None);
+        let row: Vec<u8> = (0..16).collect();
+        assert_eq!(
+            transformed(&rgba, Transformations::STRIP_16, &row),
+            [0, 2, 4, 6, 8, 10, 12, 14]
+        );
+        assert_eq!(
+            transformed(&rgba, Transformations::STRIP_ALPHA, &row),
+            [0, 1, 2, 3, 4, 5, 8, 9, 10, 11, 12, 13]
+        );
+        assert_eq!(
+            transformed(&rgba, Transformations::SWAP_ENDIAN, &row)[..4],
+            [1, 0, 3, 2]
+        );
+        let gray = info(ColorType::Grayscale, BitDepth::Sixteen, None);
+        let row = [0x10, 0xF0, 0xF0, 0x10];
+        assert_eq!(
+            transformed(&gray, Transformations::STRIP_16, &row),
+            [0x10, 0xF0]
+        );
+        assert_eq!(
+            transformed(&gray, Transformations::SCALE_16, &row),
+            [0x11, 0xEF]
+        );
+        let gray = info(ColorType::Grayscale, BitDepth::Eight, None);
+        assert_eq!(
+            transformed(&gray, Transformations::EXPAND_16, &[1, 2]),
+            [1, 1, 2, 2]
+        );
+    }
+
+    #[test]
+    fn scale_16_rounds() {
+        for &(sample, scaled) in [
+            (0, 0),
+            (128, 0),
+            (129, 1),
+            (0x7F7F, 0x7F),
+            (0x7FFF, 0x7F),
+            (0x8080, 0x80),
+            (0xFF7F, 0xFF),
+            (0xFFFF, 0xFF),
+        ]
+        .iter()
+        {
+            assert_eq!(scale_16(sample), scaled);
+        }
+        for sample in 0..=0xFFFF {
+            let exact = f64::from(sample) * 255.0 / 65535.0;
+            assert_eq!(scale_16(sample), exact.round() as u8);
+        }
+    }
+
+    #[test]
+    fn channel_order() {
+        let rgba = info(ColorType::RGBA, BitDepth::Eight, None);
+        let row = [1, 2, 3, 4, 5, 6, 7, 8];
+        assert_eq!(
+            transformed(&rgba, Transformations::BGR, &row),
+            [3, 2, 1, 4, 7, 6, 5, 8]
+        );
+        assert_eq!(
+            transformed(&rgba, Transformations::SWAP_ALPHA, &row),
+            [4, 1, 2, 3, 8, 5, 6, 7]
+        );
+        assert_eq!(
+            transformed(&rgba, Transformations::INVERT_ALPHA, &row),
+            [1, 2, 3, 251, 5, 6, 7, 247]
+        );
+        assert_eq!(
+            transformed(
+                &rgba,
+                Transformations::BGR | Transformations::SWAP_ALPHA,
+                &row
+            ),
+            [4, 3, 2, 1, 8, 7, 6, 5]
+        );
+    }
+
+    #[test]
+    fn gray_transformations() {
+        let gray_alpha = info(ColorType::GrayscaleAlpha, BitDepth::Eight, None);
+        assert_eq!(
+            transformed(&gray_alpha, Transformations::GRAY_TO_RGB, &[1, 2, 3, 4]),
+            [1, 1, 1, 2, 3, 3, 3, 4]
+        );
+        assert_eq!(
+            transformed(&gray_alpha, Transformations::INVERT_MONO, &[1, 2, 3, 4]),
+            [254, 2, 252, 4]
+        );
+        let gray = info(ColorType::Grayscale, BitDepth::Four, Some(vec![3]));
+        assert_eq!(
+            transformed(&gray, Transformations::EXPAND, &[0x3F]),
+            [0x33, 0, 0xFF, 0xFF]
+        );
+        assert_eq!(
+            transformed(
+                &gray,
+                Transformations::EXPAND | Transformations::GRAY_TO_RGB,
+                &[0x3F]
+            ),
+            [0x33, 0x33, 0x33, 0, 0xFF, 0xFF, 0xFF, 0xFF]
+        );
+    }
+
+    #[test]
+    fn shift() {
+        let mut rgb = info(ColorType::RGB, BitDepth::Sixteen, None);
+        rgb.width = 1;
+        rgb.significant_bits = Some(vec![12, 10, 16]);
+        assert_eq!(
+            transformed(
+                &rgb,
+                Transformations::SHIFT,
+                &[0xFF, 0xF0, 0xFF, 0xC0, 0x12, 0x34]
+            ),
+            [0x0F, 0xFF, 0x03, 0xFF, 0x12, 0x34]
+        );
+
+        let mut gray = info(ColorType::Grayscale, BitDepth::Four, None);
+        gray.significant_bits = Some(vec![2]);
+        assert_eq!(transformed(&gray, Transformations::SHIFT, &[0xF7]), [0x31]);
+        assert_eq!(
+            transformed(
+                &gray,
+                Transformations::SHIFT | Transformations::PACKING,
+                &[0xF7]
+            ),
+            [3, 1]
+        );
+        assert_eq!(
+            transformed(
+                &gray,
+                Transformations::SHIFT | Transformations::EXPAND,
+                &[0xF7]
+            ),
+            [3, 1]
+        );
+
+        let mut gray_alpha = info(ColorType::GrayscaleAlpha, BitDepth::Eight, None);
+        gray_alpha.significant_bits = Some(vec![5, 8]);
+        assert_eq!(
+            transformed(
+                &gray_alpha,
+                Transformations::SHIFT | Transformations::GRAY_TO_RGB,
+                &[0xF8, 0x80, 0x08, 0xFF]
+            ),
+            [31, 31, 31, 0x80, 1, 1, 1, 0xFF]
+        );
+
+        let mut indexed = info(ColorType::Indexed, BitDepth::Eight, Some(vec![0x80]));
+        indexed.palette = Some(vec![0xF8, 0xFC, 0xF8, 0x08, 0x04, 0x08]);
+        indexed.significant_bits = Some(vec![5, 6, 5]);
+        assert_eq!(
+            transformed(
+                &indexed,
+                Transformations::SHIFT | Transformations::EXPAND,
+                &[0, 1]
+            ),
+            [31, 63, 31, 0x80, 1, 1, 1, 0xFF]
+        );
+    }
+
+    /// Every combination of read transformations outputs rows of the reported layout.
+    #[test]
+    fn output_layout_matches_rows() {
+        use crate::common::ColorType::*;
+        let layouts = [
+            (Grayscale, &[1, 2, 4, 8, 16][..]),
+            (RGB, &[8, 16][..]),
+            (Indexed, &[1, 2, 4, 8][..]),
+            (GrayscaleAlpha, &[8, 16][..]),
+            (RGBA, &[8, 16][..]),
+        ];
+        let flags = [
+            Transformations::STRIP_16,
+            Transformations::STRIP_ALPHA,
+            Transformations::PACKING,
+            Transformations::PACKSWAP,
+            Transformations::EXPAND,
+            Transformations::INVERT_MONO,
+            Transformations::BGR,
+            Transformations::SWAP_ALPHA,
+            Transformations::SWAP_ENDIAN,
+            Transformations::INVERT_ALPHA,
+            Transformations::GRAY_TO_RGB,
+            Transformations::EXPAND_16,
+            Transformations::SCALE_16,
+            Transformations::SHIFT,
+            Transformations::BACKGROUND,
+        ];
+        for &(color_type, depths) in layouts.iter() {
+            for &depth in depths {
+                let depth = BitDepth::from_u8(depth).unwrap();
+                let trns = match color_type {
+                    Indexed => Some(vec![0; 4]),
+                    Grayscale | RGB => {
+                        Some(vec![0; color_type.samples() * (depth as usize + 7) / 8])
+                    }
+                    _ => None,
+                };
+                for trns in [None, trns].iter().cloned() {
+                    let mut info = info(color_type, depth, trns);
+                    info.width = 7;
+                    let channels = if color_type == Indexed {
+                        3
+                    } else {
+                        color_type.samples()
+                    };
+                    info.significant_bits = Some(vec![1; channels]);
+                    for mask in 0..1u32 << flags.len() {
+                        let t = flags
+                            .iter()
+                            .enumerate()
+                            .filter(|&(i, _)| mask & 1 << i != 0)
+                            .fold(Transformations::IDENTITY, |t, (_, &flag)| t | flag);
+                        let (color, depth) = output_layout(&info, t);
+                        let row = vec![0; info.raw_row_length() - 1];
+                        assert_eq!(
+                            transformed(&info, t, &row).len(),
+                            color.raw_row_length_from_width(depth, 7) - 1,
+                            "{:?} {:?} {:?}",
+                            info.color_type,
+                            info.bit_depth,
+                            t
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn background() {
+        let mut gray_alpha = info(ColorType::GrayscaleAlpha, BitDepth::Eight, None);
+        gray_alpha.background_color = Some(BackgroundColor::Gray(50));
+        assert_eq!(
+            transformed(
+                &gray_alpha,
+                Transformations::BACKGROUND,
+                &[200, 128, 100, 0xFF]
+            ),
+            [125, 100]
+        );
+        // Reordering applies to the opaque output.
+        assert_eq!(
+            transformed(
+                &gray_alpha,
+                Transformations::BACKGROUND | Transformations::GRAY_TO_RGB,
+                &[200, 0, 100, 0xFF]
+            ),
+            [50, 50, 50, 100, 100, 100]
+        );
+
+        let mut gray = info(ColorType::Grayscale, BitDepth::Two, Some(vec![1]));
+        gray.background_color = Some(BackgroundColor::Gray(2));
+        assert_eq!(
+            transformed(&gray, Transformations::BACKGROUND, &[0b0111_0000]),
+            [0xAA, 0xFF]
+        );
+
+        let mut indexed = info(ColorType::Indexed, BitDepth::Eight, Some(vec![0xFF, 0]));
+        indexed.background_color = Some(BackgroundColor::Indexed(2));
+        assert_eq!(
+            transformed(&indexed, Transformations::BACKGROUND, &[0, 1]),
+            [0, 1, 2, 6, 7, 8]
+        );
+
+        // A color set by the caller, converted to the luminance for grayscale images.
+        let gray_alpha = info(ColorType::GrayscaleAlpha, BitDepth::Sixteen, None);
+        let t = Transformations::BACKGROUND;
+        let background = Background::new(&gray_alpha, t, Some((0, 0xFFFF, 0)), None);
+        let mut buf = vec![0x12, 0x34, 0, 0, 0x12, 0x34, 0xFF, 0xFF];
+        let len = transform_row(&mut buf, 2, &gray_alpha, t, None, background.as_ref()).unwrap();
+        assert_eq!(buf[..len], [0xB7, 0x13, 0x12, 0x34]);
+
+        // The background of an indexed image is corrected like its palette.
+        let gamma = GammaCorrection::new(&indexed, 1.0);
+        let background = Background::new(&indexed, t, None, Some(&gamma));
+        let mut buf = vec![0, 1, 0, 0, 0, 0, 0, 0];
+        let len = transform_row(&mut buf, 2, &indexed, t, Some(&gamma), background.as_ref());
+        let corrected: Vec<u8> = [0, 1, 2, 6, 7, 8]
+            .iter()
+            .map(|&sample| gamma.table8[sample])
+            .collect();
+        assert_eq!(buf[..len.unwrap()], corrected[..]);
+        assert!(corrected != [0, 1, 2, 6, 7, 8]);
+    }
+
+    #[test]
+    fn orientation() {
+        use crate::common::Orientation::*;
+        // 3 by 2 pixels, numbered in stored order.
+        let stored = [1, 2, 3, 4, 5, 6];
+        let expected: [(Orientation, [u8; 6]); 8] = [
+            (TopLeft, [1, 2, 3, 4, 5, 6]),
+            (TopRight, [3, 2, 1, 6, 5, 4]),
+            (BottomRight, [6, 5, 4, 3, 2, 1]),
+            (BottomLeft, [4, 5, 6, 1, 2, 3]),
+            (LeftTop, [1, 4, 2, 5, 3, 6]),
+            (RightTop, [4, 1, 5, 2, 6, 3]),
+            (RightBottom, [6, 3, 5, 2, 4, 1]),
+            (LeftBottom, [3, 6, 2, 5, 1, 4]),
+        ];
+        for &(orientation, pixels) in expected.iter() {
+            let mut out = [0; 6];
+            orient_frame(&stored, &mut out, (3, 2), 8, false, orientation);
+            assert_eq!(out, pixels, "{:?}", orientation);
+        }
+
+        // Rows of packed pixels are padded to whole bytes.
+        let mut out = [0xFF; 3];
+        orient_frame(
+            &[0b1010_0000, 0b0110_0000],
+            &mut out,
+            (3, 2),
+            1,
+            false,
+            RightTop,
+        );
+        assert_eq!(out, [0b0100_0000, 0b1000_0000, 0b1100_0000]);
+        orient_frame(
+            &[0b0000_0101, 0b0000_0110],
+            &mut out,
+            (3, 2),
+            1,
+            true,
+            RightTop,
+        );
+        assert_eq!(out, [0b0000_0010, 0b0000_0001, 0b0000_0011]);
+    }
+}
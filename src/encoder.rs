@@ -5,15 +5,25 @@ use std::borrow::Cow;
 use std::error;
 use std::fmt;
 use std::io::{self, Read, Write};
-use std::mem;
 use std::result;
 
 use crc32fast::Hasher as Crc32;
 
 use crate::chunk;
-use crate::common::{BitDepth, BytesPerPixel, ColorType, Compression, Info, ScaledFloat};
-use crate::filter::{filter, FilterType};
+use crate::common::{
+    AnimationControl, BackgroundColor, BitDepth, BlendOp, BytesPerPixel, ChunkPosition,
+    CodingIndependentCodePoints, ColorType, Compression, ContentLightLevelInfo, DisposeOp,
+    FrameControl, IccProfile, ImageOffset, Info, MasteringDisplayColorVolume, PhysicalScale,
+    PixelCalibration, PixelDimensions, ScaledFloat, SourceChromaticities, SrgbRenderingIntent,
+    StereoLayout, SuggestedPalette, SuggestedPaletteEntry, Time, UnknownChunk,
+};
+use crate::filter::{filter, filter_adaptive, AdaptiveFilterType, FilterType};
+use crate::reduction;
+use crate::text_metadata::{
+    encode_iso_8859_1, encode_keyword, EncodableTextChunk, ITXtChunk, TEXtChunk, ZTXtChunk,
+};
 use crate::traits::WriteBytesExt;
+use crate::utils::{extract_pass_line, Adam7Iterator};
 
 pub type Result<T> = result::Result<T, EncodingError>;
 
@@ -57,6 +67,9 @@ impl From<EncodingError> for io::Error {
 pub struct Encoder<W: Write> {
     w: W,
     info: Info,
+    sep_def_img: bool,
+    reduce: bool,
+    image_modified: bool,
 }
 
 impl<W: Write> Encoder<W> {
@@ -64,7 +77,13 @@ impl<W: Write> Encoder<W> {
         let mut info = Info::default();
         info.width = width;
         info.height = height;
-        Encoder { w, info }
+        Encoder {
+            w,
+            info,
+            sep_def_img: false,
+            reduce: false,
+            image_modified: false,
+        }
     }
 
     pub fn set_palette(&mut self, palette: Vec<u8>) {
@@ -89,8 +108,349 @@ impl<W: Write> Encoder<W> {
         self.info.source_chromaticities = Some(source_chromaticities);
     }
 
+    /// Set the physical pixel dimensions, written in a pHYs chunk.
+    ///
+    /// `PixelDimensions::from_dpi` converts a resolution in dots per inch. Values above 2^31 - 1
+    /// are rejected when writing the header.
+    pub fn set_pixel_dims(&mut self, pixel_dims: PixelDimensions) {
+        self.info.pixel_dims = Some(pixel_dims);
+    }
+
+    /// Set the position of the image on a page or within a larger image, written in an oFFs
+    /// chunk.
+    pub fn set_offset(&mut self, offset: ImageOffset) {
+        self.info.offset = Some(offset);
+    }
+
+    /// Set the calibration of the samples to physical values, written in a pCAL chunk.
+    ///
+    /// The name follows the same restrictions as the keyword of a text chunk and the unit must be
+    /// representable in Latin-1. The original samples must differ and the number of finite
+    /// parameters must match the equation type.
+    pub fn set_pixel_calibration(&mut self, calibration: PixelCalibration) -> Result<()> {
+        encode_keyword(&calibration.name)?;
+        match encode_iso_8859_1(&calibration.unit) {
+            Some(ref unit) if !unit.contains(&0) => {}
+            _ => return Err(EncodingError::Format("pCAL unit is not Latin-1".into())),
+        }
+        let parameters = &calibration.parameters;
+        if calibration.original_zero == calibration.original_max
+            || parameters.len() != calibration.equation_type.parameter_count()
+            || !parameters.iter().all(|p| p.is_finite())
+        {
+            return Err(EncodingError::Format(
+                format!(
+                    "Invalid pCAL calibration for equation type '{:?}'",
+                    calibration.equation_type
+                )
+                .into(),
+            ));
+        }
+        self.info.pixel_calibration = Some(calibration);
+        Ok(())
+    }
+
+    /// Set the physical size of the pixels of the image subject, written in an sCAL chunk.
+    ///
+    /// The pixel width and height must be positive and finite.
+    pub fn set_physical_scale(&mut self, scale: PhysicalScale) -> Result<()> {
+        let valid = |size: f64| size > 0.0 && size.is_finite();
+        if !valid(scale.pixel_width) || !valid(scale.pixel_height) {
+            return Err(EncodingError::Format(
+                format!(
+                    "Invalid sCAL pixel size {}x{}",
+                    scale.pixel_width, scale.pixel_height
+                )
+                .into(),
+            ));
+        }
+        self.info.physical_scale = Some(scale);
+        Ok(())
+    }
+
+    /// Set the layout of a stereo image, written in an sTER chunk.
+    pub fn set_stereo_layout(&mut self, layout: StereoLayout) {
+        self.info.stereo_layout = Some(layout);
+    }
+
+    /// Set the number of significant bits of each channel, written in an sBIT chunk.
+    ///
+    /// There is one entry per channel of the color type, three for the palette of indexed images,
+    /// each between 1 and the bit depth, which is checked when writing the header. Images with
+    /// significant bits are not changed by lossless reduction.
+    pub fn set_significant_bits(&mut self, bits: Vec<u8>) {
+        self.info.significant_bits = Some(bits);
+    }
+
+    /// Set the preferred background color, written in a bKGD chunk.
+    ///
+    /// The variant must match the color type and the samples must fit the bit depth, indices must
+    /// refer to an entry of the palette, which is checked when writing the header. Images with a
+    /// background color are not changed by lossless reduction.
+    pub fn set_background_color(&mut self, color: BackgroundColor) {
+        self.info.background_color = Some(color);
+    }
+
+    /// Set the time of the last modification, written in a tIME chunk.
+    ///
+    /// The fields are checked when writing the header, `Time::from_system_time` converts the
+    /// current time.
+    pub fn set_last_modified(&mut self, time: Time) {
+        self.info.last_modified = Some(time);
+    }
+
+    /// Set the Exif metadata, written in an eXIf chunk.
+    ///
+    /// The data starts with the TIFF header, `II` or `MM`, without the `Exif` prefix of JPEG
+    /// files, which is checked when writing the header.
+    pub fn set_exif_metadata(&mut self, exif: Vec<u8>) {
+        self.info.exif_metadata = Some(exif);
+    }
+
+    /// Identify the color space with coding-independent code points, written in a cICP chunk.
+    ///
+    /// The matrix coefficients must be 0, which is checked when writing the header.
+    pub fn set_coding_independent_code_points(&mut self, cicp: CodingIndependentCodePoints) {
+        self.info.coding_independent_code_points = Some(cicp);
+    }
+
+    /// Set the color volume of the mastering display, written in an mDCv chunk.
+    pub fn set_mastering_display_color_volume(&mut self, mdcv: MasteringDisplayColorVolume) {
+        self.info.mastering_display_color_volume = Some(mdcv);
+    }
+
+    /// Set the light level of the content, written in a cLLi chunk.
+    pub fn set_content_light_level(&mut self, clli: ContentLightLevelInfo) {
+        self.info.content_light_level = Some(clli);
+    }
+
+    /// Embed an ICC profile, written compressed in an iCCP chunk.
+    ///
+    /// The name follows the same restrictions as the keyword of a text chunk. An image with an
+    /// ICC profile must not contain an sRGB chunk.
+    pub fn set_icc_profile(&mut self, name: String, profile: Vec<u8>) -> Result<()> {
+        if self.info.srgb.is_some() {
+            return Err(EncodingError::Format(
+                "an ICC profile must not be combined with sRGB".into(),
+            ));
+        }
+        encode_keyword(&name)?;
+        self.info.icc_profile = Some(IccProfile { name, profile });
+        Ok(())
+    }
+
+    /// Mark the image as being in the sRGB color space, written in an sRGB chunk.
+    ///
+    /// An image in the sRGB color space must not contain an ICC profile.
+    pub fn set_srgb(&mut self, rendering_intent: SrgbRenderingIntent) -> Result<()> {
+        if self.info.icc_profile.is_some() {
+            return Err(EncodingError::Format(
+                "sRGB must not be combined with an ICC profile".into(),
+            ));
+        }
+        self.info.srgb = Some(rendering_intent);
+        Ok(())
+    }
+
+    /// Like `set_srgb` and additionally set the gamma and chromaticities to the values the
+    /// specification recommends along with sRGB, for decoders that do not support sRGB.
+    pub fn set_srgb_with_fallbacks(&mut self, rendering_intent: SrgbRenderingIntent) -> Result<()> {
+        self.set_srgb(rendering_intent)?;
+        self.info.source_gamma = Some(ScaledFloat::SRGB_GAMMA);
+        self.info.source_chromaticities = Some(SourceChromaticities::SRGB);
+        Ok(())
+    }
+
+    /// Set the usage frequency of each palette entry, written in a hIST chunk.
+    ///
+    /// There must be one entry per palette entry, which is checked when writing the header.
+    pub fn set_histogram(&mut self, histogram: Vec<u16>) {
+        self.info.histogram = Some(histogram);
+    }
+
+    /// Add a suggested palette, written in an sPLT chunk before the image data.
+    ///
+    /// The name follows the same restrictions as the keyword of a text chunk and must differ from
+    /// those of the other suggested palettes. The samples of palettes with a sample depth of
+    /// `Eight` must fit 8 bits.
+    pub fn add_suggested_palette(&mut self, palette: SuggestedPalette) -> Result<()> {
+        encode_keyword(&palette.name)?;
+        let max = match palette.sample_depth {
+            BitDepth::Eight => 0xFF,
+            BitDepth::Sixteen => 0xFFFF,
+            depth => {
+                return Err(EncodingError::Format(
+                    format!("Invalid sPLT sample depth '{:?}'", depth).into(),
+                ))
+            }
+        };
+        let fits = |e: &SuggestedPaletteEntry| {
+            e.red <= max && e.green <= max && e.blue <= max && e.alpha <= max
+        };
+        if !palette.entries.iter().all(fits) {
+            return Err(EncodingError::Format(
+                "sPLT samples exceed the sample depth".into(),
+            ));
+        }
+        if self
+            .info
+            .suggested_palettes
+            .iter()
+            .any(|other| other.name == palette.name)
+        {
+            return Err(EncodingError::Format(
+                format!("Duplicate sPLT palette name '{}'", palette.name).into(),
+            ));
+        }
+        self.info.suggested_palettes.push(palette);
+        Ok(())
+    }
+
+    /// Add a tEXt chunk to be written before the image data.
+    ///
+    /// The keyword must consist of 1-79 printable Latin-1 characters without leading, trailing or
+    /// consecutive spaces, the text must be representable in Latin-1.
+    pub fn add_text_chunk(&mut self, keyword: String, text: String) -> Result<()> {
+        let text_chunk = TEXtChunk::new(keyword, text);
+        text_chunk.validate()?;
+        self.info.uncompressed_latin1_text.push(text_chunk);
+        Ok(())
+    }
+
+    /// Add a zTXt chunk to be written before the image data.
+    ///
+    /// The same restrictions as for `add_text_chunk` apply. The text is compressed when it is
+    /// written.
+    pub fn add_ztxt_chunk(&mut self, keyword: String, text: String) -> Result<()> {
+        let text_chunk = ZTXtChunk::new(keyword, text);
+        text_chunk.validate()?;
+        self.info.compressed_latin1_text.push(text_chunk);
+        Ok(())
+    }
+
+    /// Add an uncompressed iTXt chunk to be written before the image data.
+    ///
+    /// The keyword must consist of 1-79 printable Latin-1 characters while the text can be
+    /// arbitrary UTF-8. To write an iTXt chunk with a language tag, translated keyword or
+    /// compressed text use `Writer::write_text_chunk`.
+    pub fn add_itxt_chunk(&mut self, keyword: String, text: String) -> Result<()> {
+        let text_chunk = ITXtChunk::new(keyword, text);
+        text_chunk.validate()?;
+        self.info.utf8_text.push(text_chunk);
+        Ok(())
+    }
+
+    /// Add an ancillary chunk to be written unchanged at its position, such as one preserved by
+    /// `Decoder::set_preserve_unknown_chunks`.
+    ///
+    /// The chunk type must consist of ASCII letters with the reserved bit unset, critical chunks
+    /// can not be added.
+    pub fn add_unknown_chunk(&mut self, chunk: UnknownChunk) -> Result<()> {
+        let name = chunk.chunk_type;
+        if !name.iter().all(u8::is_ascii_alphabetic)
+            || chunk::is_critical(name)
+            || chunk::reserved_set(name)
+        {
+            return Err(EncodingError::Format(
+                format!(
+                    "Invalid ancillary chunk type '{}'",
+                    String::from_utf8_lossy(&name)
+                )
+                .into(),
+            ));
+        }
+        self.info.unknown_chunks.push(chunk);
+        Ok(())
+    }
+
+    /// Set whether the image data differs from that of the image the unknown chunks were copied
+    /// from.
+    ///
+    /// Unknown chunks that are not safe to copy depend on the image data and are left out of a
+    /// modified image. Writing a reduced image with `set_lossless_reduction` also counts as a
+    /// modification if the reduction changes the image data.
+    pub fn set_image_modified(&mut self, modified: bool) {
+        self.image_modified = modified;
+    }
+
+    /// Mark the image as an animated png (APNG) of `num_frames` frames, played `num_plays` times.
+    ///
+    /// A `num_plays` of 0 loops the animation infinitely. Each frame covers the whole image with a
+    /// delay of 1/30 s unless changed through the frame control setters of the `Encoder`, for the
+    /// first frame, or the `Writer`, for all following frames. The writer checks on `finish` that
+    /// exactly `num_frames` frames have been written.
+    pub fn set_animated(&mut self, num_frames: u32, num_plays: u32) -> Result<()> {
+        if num_frames == 0 {
+            return Err(EncodingError::Format(
+                "animation must consist of at least one frame".into(),
+            ));
+        }
+        self.info.animation_control = Some(AnimationControl {
+            num_frames,
+            num_plays,
+        });
+        self.info.frame_control = Some(FrameControl {
+            width: self.info.width,
+            height: self.info.height,
+            ..FrameControl::default()
+        });
+        Ok(())
+    }
+
+    /// Set whether the default image is separate from the animation.
+    ///
+    /// A separate default image is only shown by decoders without APNG support and is written in
+    /// addition to the `num_frames` frames of the animation. By default the first image written
+    /// is also the first frame of the animation.
+    pub fn set_sep_def_img(&mut self, sep_def_img: bool) -> Result<()> {
+        animated_frame_control(&mut self.info)?;
+        self.sep_def_img = sep_def_img;
+        Ok(())
+    }
+
+    /// Set the delay of the first frame as the fraction `numerator / denominator` seconds.
+    ///
+    /// A denominator of 0 is treated as 100 by decoders.
+    pub fn set_frame_delay(&mut self, numerator: u16, denominator: u16) -> Result<()> {
+        let frame_control = animated_frame_control(&mut self.info)?;
+        frame_control.delay_num = numerator;
+        frame_control.delay_den = denominator;
+        Ok(())
+    }
+
+    /// Set how the area of the first frame is disposed of before the next frame is rendered.
+    pub fn set_dispose_op(&mut self, op: DisposeOp) -> Result<()> {
+        animated_frame_control(&mut self.info)?.dispose_op = op;
+        Ok(())
+    }
+
+    /// Set how the first frame is blended into the output buffer.
+    pub fn set_blend_op(&mut self, op: BlendOp) -> Result<()> {
+        animated_frame_control(&mut self.info)?.blend_op = op;
+        Ok(())
+    }
+
+    /// Set whether the image is written in its smallest lossless representation.
+    ///
+    /// The image data passed to `Writer::write_image_data` is examined for unused channels,
+    /// unused precision and the number of distinct colors. It is then written with the smallest
+    /// color type and bit depth that represent it exactly, which may add a generated palette.
+    /// Since the header depends on the data, no chunk is written before the image data. It is an
+    /// error to use the stream writer or to write chunks before the image data in this mode, and
+    /// animated images are not supported.
+    pub fn set_lossless_reduction(&mut self, reduce: bool) {
+        self.reduce = reduce;
+    }
+
     pub fn write_header(self) -> Result<Writer<W>> {
-        Writer::new(self.w, self.info).init()
+        Writer::new(
+            self.w,
+            self.info,
+            self.sep_def_img,
+            self.reduce,
+            self.image_modified,
+        )
+        .init()
     }
 
     /// Set the color of the encoded image.
@@ -107,6 +467,14 @@ impl<W: Write> Encoder<W> {
         self.info.bit_depth = depth;
     }
 
+    /// Set whether the image is written with Adam7 interlacing.
+    ///
+    /// Interlaced images can be displayed progressively while they are loaded, at the cost of a
+    /// slightly larger file. The image data is supplied in the usual row order in any case.
+    pub fn set_interlaced(&mut self, interlaced: bool) {
+        self.info.interlaced = interlaced;
+    }
+
     /// Set compression parameters.
     ///
     /// Accepts a `Compression` or any type that can transform into a `Compression`. Notably `deflate::Compression` and
@@ -126,17 +494,50 @@ impl<W: Write> Encoder<W> {
     pub fn set_filter(&mut self, filter: FilterType) {
         self.info.filter = filter;
     }
+
+    /// Set the heuristic that chooses a filter for each row individually.
+    ///
+    /// Adaptive filtering usually compresses better than any single filter. With the default
+    /// [`AdaptiveFilterType::NonAdaptive`] the filter set with `set_filter` is used for all rows.
+    ///
+    /// [`AdaptiveFilterType::NonAdaptive`]: enum.AdaptiveFilterType.html#variant.NonAdaptive
+    pub fn set_adaptive_filter(&mut self, adaptive_filter: AdaptiveFilterType) {
+        self.info.adaptive_filter = adaptive_filter;
+    }
 }
 
 /// PNG writer
 pub struct Writer<W: Write> {
     w: W,
     info: Info,
+    /// Whether the first image is a default image that is not part of the animation.
+    sep_def_img: bool,
+    /// The sequence number of the next fcTL or fdAT chunk.
+    sequence_number: u32,
+    /// The number of images whose data has been started, including a separate default image.
+    images_written: u32,
+    /// Whether the chunks before the image data have been written, this is delayed until the image
+    /// data is known when reducing it.
+    header_written: bool,
+    /// Whether the image data is to be reduced, which is only known once the header is written.
+    reduce: bool,
+    /// Whether unknown chunks that are not safe to copy are left out.
+    image_modified: bool,
+    iend_written: bool,
 }
 
 const DEFAULT_BUFFER_LENGTH: usize = 4 * 1024;
 
-fn write_chunk<W: Write>(mut w: W, name: [u8; 4], data: &[u8]) -> Result<()> {
+fn animated_frame_control(info: &mut Info) -> Result<&mut FrameControl> {
+    match (&info.animation_control, &mut info.frame_control) {
+        (Some(_), Some(frame_control)) => Ok(frame_control),
+        _ => Err(EncodingError::Format(
+            "frame control requires an animated image".into(),
+        )),
+    }
+}
+
+pub(crate) fn write_chunk<W: Write>(mut w: W, name: [u8; 4], data: &[u8]) -> Result<()> {
     w.write_be(data.len() as u32)?;
     w.write_all(&name)?;
     w.write_all(data)?;
@@ -148,8 +549,18 @@ fn write_chunk<W: Write>(mut w: W, name: [u8; 4], data: &[u8]) -> Result<()> {
 }
 
 impl<W: Write> Writer<W> {
-    fn new(w: W, info: Info) -> Writer<W> {
-        Writer { w, info }
+    fn new(w: W, info: Info, sep_def_img: bool, reduce: bool, image_modified: bool) -> Writer<W> {
+        Writer {
+            w,
+            info,
+            sep_def_img,
+            sequence_number: 0,
+            images_written: 0,
+            header_written: false,
+            reduce,
+            image_modified,
+            iend_written: false,
+        }
     }
 
     fn init(mut self) -> Result<Self> {
@@ -176,6 +587,96 @@ impl<W: Write> Writer<W> {
             ));
         }
 
+        if let Some(bits) = &self.info.significant_bits {
+            if !self.info.significant_bits_valid(bits) {
+                return Err(EncodingError::Format(
+                    format!(
+                        "Invalid significant bits {:?} for color-type '{:?}'",
+                        bits, self.info.color_type
+                    )
+                    .into(),
+                ));
+            }
+        }
+
+        if let Some(color) = self.info.background_color {
+            if !self.info.background_color_valid(color) {
+                return Err(EncodingError::Format(
+                    format!(
+                        "Invalid background color {:?} for color-type '{:?}'",
+                        color, self.info.color_type
+                    )
+                    .into(),
+                ));
+            }
+        }
+
+        if let Some(time) = self.info.last_modified {
+            if !time.is_valid() {
+                return Err(EncodingError::Format(
+                    format!("Invalid modification time {:?}", time).into(),
+                ));
+            }
+        }
+
+        if let Some(pixel_dims) = self.info.pixel_dims {
+            let max = i32::max_value() as u32;
+            if pixel_dims.xppu > max || pixel_dims.yppu > max {
+                return Err(EncodingError::Format(
+                    format!("Invalid pixel dimensions {:?}", pixel_dims).into(),
+                ));
+            }
+        }
+
+        if let Some(exif) = &self.info.exif_metadata {
+            if !exif.starts_with(b"II") && !exif.starts_with(b"MM") {
+                return Err(EncodingError::Format(
+                    "Exif metadata must start with a TIFF header".into(),
+                ));
+            }
+        }
+
+        if let Some(cicp) = self.info.coding_independent_code_points {
+            if cicp.matrix_coefficients != 0 {
+                return Err(EncodingError::Format(
+                    format!(
+                        "Invalid cICP matrix coefficients '{}', only RGB is supported",
+                        cicp.matrix_coefficients
+                    )
+                    .into(),
+                ));
+            }
+        }
+
+        if let Some(histogram) = &self.info.histogram {
+            let entries = self.info.palette.as_ref().map(|palette| palette.len() / 3);
+            if entries != Some(histogram.len()) {
+                return Err(EncodingError::Format(
+                    format!(
+                        "Histogram of {} entries does not match the palette",
+                        histogram.len()
+                    )
+                    .into(),
+                ));
+            }
+        }
+
+        if self.reduce {
+            if self.info.animation_control.is_some() {
+                return Err(EncodingError::Format(
+                    "lossless reduction of animated images is not supported".into(),
+                ));
+            }
+            return Ok(self);
+        }
+
+        self.write_header_chunks()?;
+        Ok(self)
+    }
+
+    /// Write the signature and all chunks preceding the image data.
+    fn write_header_chunks(&mut self) -> Result<()> {
+        self.header_written = true;
         self.w.write_all(&[137, 80, 78, 71, 13, 10, 26, 10])?;
         let mut data = [0; 13];
         (&mut data[..]).write_be(self.info.width)?;
@@ -183,7 +684,53 @@ impl<W: Write> Writer<W> {
         data[8] = self.info.bit_depth as u8;
         data[9] = self.info.color_type as u8;
         data[12] = if self.info.interlaced { 1 } else { 0 };
-        self.write_chunk(chunk::IHDR, &data)?;
+        write_chunk(&mut self.w, chunk::IHDR, &data)?;
+
+        if let Some(icc) = &self.info.icc_profile {
+            let mut data = encode_keyword(&icc.name)?;
+            // Null separator and compression method, only deflate is defined.
+            data.extend_from_slice(&[0, 0]);
+            data.extend(deflate::deflate_bytes_zlib(&icc.profile));
+            write_chunk(&mut self.w, chunk::iCCP, &data)?;
+        }
+
+        if let Some(rendering_intent) = self.info.srgb {
+            write_chunk(&mut self.w, chunk::sRGB, &[rendering_intent as u8])?;
+        }
+
+        if let Some(bits) = &self.info.significant_bits {
+            write_chunk(&mut self.w, chunk::sBIT, bits)?;
+        }
+
+        if let Some(cicp) = self.info.coding_independent_code_points {
+            let data = [
+                cicp.color_primaries,
+                cicp.transfer_function,
+                cicp.matrix_coefficients,
+                cicp.is_video_full_range_image as u8,
+            ];
+            write_chunk(&mut self.w, chunk::cICP, &data)?;
+        }
+
+        if let Some(mdcv) = self.info.mastering_display_color_volume {
+            let mut data = Vec::with_capacity(24);
+            for &(x, y) in [mdcv.red, mdcv.green, mdcv.blue, mdcv.white].iter() {
+                data.write_be(x)?;
+                data.write_be(y)?;
+            }
+            data.write_be(mdcv.max_luminance)?;
+            data.write_be(mdcv.min_luminance)?;
+            write_chunk(&mut self.w, chunk::mDCv, &data)?;
+        }
+
+        if let Some(clli) = self.info.content_light_level {
+            let mut data = [0; 8];
+            (&mut data[..]).write_be(clli.max_content_light_level)?;
+            (&mut data[4..]).write_be(clli.max_frame_average_light_level)?;
+            write_chunk(&mut self.w, chunk::cLLi, &data)?;
+        }
+
+        self.write_unknown_chunks(ChunkPosition::BeforePlte)?;
 
         if let Some(p) = &self.info.palette {
             write_chunk(&mut self.w, chunk::PLTE, p)?;
@@ -193,6 +740,42 @@ impl<W: Write> Writer<W> {
             write_chunk(&mut self.w, chunk::tRNS, t)?;
         }
 
+        if let Some(color) = self.info.background_color {
+            let data = match color {
+                BackgroundColor::Indexed(index) => vec![index],
+                BackgroundColor::Gray(gray) => gray.to_be_bytes().to_vec(),
+                BackgroundColor::Rgb(r, g, b) => [r, g, b]
+                    .iter()
+                    .flat_map(|s| s.to_be_bytes().to_vec())
+                    .collect(),
+            };
+            write_chunk(&mut self.w, chunk::bKGD, &data)?;
+        }
+
+        if let Some(histogram) = &self.info.histogram {
+            let data: Vec<u8> = histogram
+                .iter()
+                .flat_map(|frequency| frequency.to_be_bytes().to_vec())
+                .collect();
+            write_chunk(&mut self.w, chunk::hIST, &data)?;
+        }
+
+        for palette in &self.info.suggested_palettes {
+            let mut data = encode_keyword(&palette.name)?;
+            data.push(0);
+            data.push(palette.sample_depth as u8);
+            for entry in &palette.entries {
+                for &sample in [entry.red, entry.green, entry.blue, entry.alpha].iter() {
+                    match palette.sample_depth {
+                        BitDepth::Eight => data.push(sample as u8),
+                        _ => data.extend_from_slice(&sample.to_be_bytes()),
+                    }
+                }
+                data.extend_from_slice(&entry.frequency.to_be_bytes());
+            }
+            write_chunk(&mut self.w, chunk::sPLT, &data)?;
+        }
+
         if let Some(g) = &self.info.source_gamma {
             write_chunk(&mut self.w, chunk::gAMA, &g.into_scaled().to_be_bytes())?;
         }
@@ -202,7 +785,113 @@ impl<W: Write> Writer<W> {
             write_chunk(&mut self.w, chunk::cHRM, &enc)?;
         }
 
-        Ok(self)
+        if let Some(pixel_dims) = self.info.pixel_dims {
+            let mut data = [0; 9];
+            (&mut data[..]).write_be(pixel_dims.xppu)?;
+            (&mut data[4..]).write_be(pixel_dims.yppu)?;
+            data[8] = pixel_dims.unit as u8;
+            write_chunk(&mut self.w, chunk::pHYs, &data)?;
+        }
+
+        if let Some(offset) = self.info.offset {
+            let mut data = [0; 9];
+            (&mut data[..]).write_be(offset.x)?;
+            (&mut data[4..]).write_be(offset.y)?;
+            data[8] = offset.unit as u8;
+            write_chunk(&mut self.w, chunk::oFFs, &data)?;
+        }
+
+        if let Some(calibration) = &self.info.pixel_calibration {
+            let mut data = encode_keyword(&calibration.name)?;
+            data.push(0);
+            data.write_be(calibration.original_zero)?;
+            data.write_be(calibration.original_max)?;
+            data.push(calibration.equation_type as u8);
+            data.push(calibration.parameters.len() as u8);
+            // Checked by `set_pixel_calibration`.
+            data.extend(encode_iso_8859_1(&calibration.unit).unwrap_or_default());
+            for parameter in &calibration.parameters {
+                data.push(0);
+                data.extend_from_slice(parameter.to_string().as_bytes());
+            }
+            write_chunk(&mut self.w, chunk::pCAL, &data)?;
+        }
+
+        if let Some(scale) = self.info.physical_scale {
+            let mut data = vec![scale.unit as u8];
+            data.extend_from_slice(scale.pixel_width.to_string().as_bytes());
+            data.push(0);
+            data.extend_from_slice(scale.pixel_height.to_string().as_bytes());
+            write_chunk(&mut self.w, chunk::sCAL, &data)?;
+        }
+
+        if let Some(layout) = self.info.stereo_layout {
+            write_chunk(&mut self.w, chunk::sTER, &[layout as u8])?;
+        }
+
+        if let Some(exif) = &self.info.exif_metadata {
+            write_chunk(&mut self.w, chunk::eXIf, exif)?;
+        }
+
+        if let Some(time) = self.info.last_modified {
+            let mut data = time.year.to_be_bytes().to_vec();
+            data.extend_from_slice(&[time.month, time.day, time.hour, time.minute, time.second]);
+            write_chunk(&mut self.w, chunk::tIME, &data)?;
+        }
+
+        for text_chunk in &self.info.uncompressed_latin1_text {
+            text_chunk.encode(&mut self.w)?;
+        }
+
+        for text_chunk in &self.info.compressed_latin1_text {
+            text_chunk.encode(&mut self.w)?;
+        }
+
+        for text_chunk in &self.info.utf8_text {
+            text_chunk.encode(&mut self.w)?;
+        }
+
+        if let Some(actl) = self.info.animation_control {
+            let mut data = [0; 8];
+            (&mut data[..]).write_be(actl.num_frames)?;
+            (&mut data[4..]).write_be(actl.num_plays)?;
+            write_chunk(&mut self.w, chunk::acTL, &data)?;
+        }
+
+        self.write_unknown_chunks(ChunkPosition::BeforeIdat)?;
+
+        Ok(())
+    }
+
+    /// Write the unknown chunks at `position`, leaving out those that are not safe to copy into a
+    /// modified image.
+    fn write_unknown_chunks(&mut self, position: ChunkPosition) -> Result<()> {
+        for unknown in &self.info.unknown_chunks {
+            let copy = !self.image_modified || chunk::safe_to_copy(unknown.chunk_type);
+            if unknown.position == position && copy {
+                write_chunk(&mut self.w, unknown.chunk_type, &unknown.data)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the unknown chunks following the image data and the IEND chunk.
+    fn write_trailer(&mut self) -> Result<()> {
+        self.iend_written = true;
+        self.check_header_written()?;
+        self.write_unknown_chunks(ChunkPosition::AfterIdat)?;
+        self.write_chunk(chunk::IEND, &[])
+    }
+
+    /// Fail if the header has not been written yet, because the image data is still to be reduced.
+    fn check_header_written(&self) -> Result<()> {
+        if self.header_written {
+            Ok(())
+        } else {
+            Err(EncodingError::Format(
+                "lossless reduction requires the image data to be written first".into(),
+            ))
+        }
     }
 
     #[rustfmt::skip]
@@ -227,13 +916,184 @@ impl<W: Write> Writer<W> {
         ]
     }
 
-    pub fn write_chunk(&mut self, name: [u8; 4], data: &[u8]) -> Result<()> {
-        write_chunk(&mut self.w, name, data)
+    pub fn write_chunk(&mut self, name: [u8; 4], data: &[u8]) -> Result<()> {
+        self.check_header_written()?;
+        let conflicting = match name {
+            chunk::sRGB => self.info.icc_profile.is_some(),
+            chunk::iCCP => self.info.srgb.is_some(),
+            _ => false,
+        };
+        if conflicting {
+            return Err(EncodingError::Format(
+                "sRGB and iCCP chunks must not be written together".into(),
+            ));
+        }
+        let misplaced = match name {
+            chunk::cICP => self.info.palette.is_some() || self.images_written > 0,
+            chunk::mDCv | chunk::cLLi => self.images_written > 0,
+            _ => false,
+        };
+        if misplaced {
+            return Err(EncodingError::Format(
+                format!(
+                    "{} chunk must precede the image data",
+                    String::from_utf8_lossy(&name)
+                )
+                .into(),
+            ));
+        }
+        write_chunk(&mut self.w, name, data)
+    }
+
+    /// Writes a text chunk of any kind, before or after the image data.
+    ///
+    /// The chunk is validated and its text compressed if required.
+    pub fn write_text_chunk<T: EncodableTextChunk>(&mut self, text_chunk: &T) -> Result<()> {
+        self.check_header_written()?;
+        text_chunk.encode(&mut self.w)
+    }
+
+    /// Set the delay of the next frame as the fraction `numerator / denominator` seconds.
+    ///
+    /// A denominator of 0 is treated as 100 by decoders.
+    pub fn set_frame_delay(&mut self, numerator: u16, denominator: u16) -> Result<()> {
+        let frame_control = animated_frame_control(&mut self.info)?;
+        frame_control.delay_num = numerator;
+        frame_control.delay_den = denominator;
+        Ok(())
+    }
+
+    /// Set the size of the next frame.
+    ///
+    /// The frame, placed at its offset, must lie within the image. The image data of the frame
+    /// must be of this size.
+    pub fn set_frame_dimension(&mut self, width: u32, height: u32) -> Result<()> {
+        let frame_control = animated_frame_control(&mut self.info)?;
+        frame_control.width = width;
+        frame_control.height = height;
+        Ok(())
+    }
+
+    /// Reset the size of the next frame to the size of the image.
+    pub fn reset_frame_dimension(&mut self) -> Result<()> {
+        let (width, height) = self.info.size();
+        self.set_frame_dimension(width, height)
+    }
+
+    /// Set the offset of the next frame within the image.
+    pub fn set_frame_position(&mut self, x: u32, y: u32) -> Result<()> {
+        let frame_control = animated_frame_control(&mut self.info)?;
+        frame_control.x_offset = x;
+        frame_control.y_offset = y;
+        Ok(())
+    }
+
+    /// Reset the offset of the next frame to the top left corner of the image.
+    pub fn reset_frame_position(&mut self) -> Result<()> {
+        self.set_frame_position(0, 0)
+    }
+
+    /// Set how the area of the next frame is disposed of before the frame after it is rendered.
+    pub fn set_dispose_op(&mut self, op: DisposeOp) -> Result<()> {
+        animated_frame_control(&mut self.info)?.dispose_op = op;
+        Ok(())
+    }
+
+    /// Set how the next frame is blended into the output buffer.
+    pub fn set_blend_op(&mut self, op: BlendOp) -> Result<()> {
+        animated_frame_control(&mut self.info)?.blend_op = op;
+        Ok(())
+    }
+
+    /// The size of the image that is written next.
+    fn next_image_size(&self) -> (u32, u32) {
+        match self.info.frame_control {
+            Some(fctl) if !(self.sep_def_img && self.images_written == 0) => {
+                (fctl.width, fctl.height)
+            }
+            _ => self.info.size(),
+        }
+    }
+
+    /// Start the next image, writing its frame control if it is part of an animation.
+    fn begin_image(&mut self) -> Result<()> {
+        self.check_header_written()?;
+        let (actl, mut fctl) = match (self.info.animation_control, self.info.frame_control) {
+            (Some(actl), Some(fctl)) => (actl, fctl),
+            _ => {
+                self.images_written += 1;
+                return Ok(());
+            }
+        };
+
+        let is_default_image = self.images_written == 0;
+        if self.images_written >= actl.num_frames + self.sep_def_img as u32 {
+            return Err(EncodingError::Format(
+                "all frames of the animation have already been written".into(),
+            ));
+        }
+
+        if !(is_default_image && self.sep_def_img) {
+            if fctl.width == 0 || fctl.height == 0 {
+                return Err(EncodingError::Format("Zero frame size not allowed".into()));
+            }
+            let right = fctl.x_offset.checked_add(fctl.width);
+            let bottom = fctl.y_offset.checked_add(fctl.height);
+            match (right, bottom) {
+                (Some(right), Some(bottom))
+                    if right <= self.info.width && bottom <= self.info.height => {}
+                _ => {
+                    return Err(EncodingError::Format(
+                        "frame does not fit within the image".into(),
+                    ))
+                }
+            }
+            if is_default_image && (fctl.width, fctl.height) != self.info.size() {
+                return Err(EncodingError::Format(
+                    "a default image that is part of the animation must cover the whole image"
+                        .into(),
+                ));
+            }
+
+            fctl.set_seq_num(self.sequence_number);
+            self.sequence_number += 1;
+            let mut data = [0; 26];
+            (&mut data[..]).write_be(fctl.sequence_number)?;
+            (&mut data[4..]).write_be(fctl.width)?;
+            (&mut data[8..]).write_be(fctl.height)?;
+            (&mut data[12..]).write_be(fctl.x_offset)?;
+            (&mut data[16..]).write_be(fctl.y_offset)?;
+            (&mut data[20..]).write_be(fctl.delay_num)?;
+            (&mut data[22..]).write_be(fctl.delay_den)?;
+            data[24] = fctl.dispose_op as u8;
+            data[25] = fctl.blend_op as u8;
+            self.write_chunk(chunk::fcTL, &data)?;
+        }
+
+        self.images_written += 1;
+        Ok(())
+    }
+
+    /// Write compressed data of the current image, as IDAT for the first image and as fdAT
+    /// otherwise.
+    fn write_image_chunk(&mut self, data: &[u8]) -> Result<()> {
+        if self.images_written <= 1 {
+            return self.write_chunk(chunk::IDAT, data);
+        }
+        let mut fdat = Vec::with_capacity(data.len() + 4);
+        fdat.write_be(self.sequence_number)?;
+        fdat.extend_from_slice(data);
+        self.sequence_number += 1;
+        self.write_chunk(chunk::fdAT, &fdat)
     }
 
     /// Writes the image data.
+    ///
+    /// For an animated image, each call writes one frame with the frame control set up to this
+    /// point. The data must then be of the size of the frame.
     pub fn write_image_data(&mut self, data: &[u8]) -> Result<()> {
-        const MAX_CHUNK_LEN: u32 = (1u32 << 31) - 1;
+        // Leave room for the sequence number of fdAT chunks.
+        const MAX_CHUNK_LEN: u32 = (1u32 << 31) - 1 - 4;
 
         if self.info.color_type == ColorType::Indexed && self.info.palette.is_none() {
             return Err(EncodingError::Format(
@@ -241,32 +1101,52 @@ impl<W: Write> Writer<W> {
             ));
         }
 
-        let bpp = self.info.bpp_in_prediction();
-        let in_len = self.info.raw_row_length() - 1;
-        let prev = vec![0; in_len];
-        let mut prev = prev.as_slice();
-        let mut current = vec![0; in_len];
-        let data_size = in_len * self.info.height as usize;
+        let (width, height) = self.next_image_size();
+        let in_len = self.info.raw_row_length_from_width(width) - 1;
+        let data_size = in_len * height as usize;
         if data_size != data.len() {
             let message = format!("wrong data size, expected {} got {}", data_size, data.len());
             return Err(EncodingError::Format(message.into()));
         }
+        let data = if self.header_written {
+            Cow::Borrowed(data)
+        } else {
+            let data = reduction::reduce(&mut self.info, data);
+            if let Cow::Owned(_) = data {
+                self.image_modified = true;
+            }
+            self.write_header_chunks()?;
+            data
+        };
+        self.begin_image()?;
         let mut zlib = deflate::write::ZlibEncoder::new(Vec::new(), self.info.compression.clone());
-        let filter_method = self.info.filter;
-        for line in data.chunks(in_len) {
-            current.copy_from_slice(&line);
-            zlib.write_all(&[filter_method as u8])?;
-            filter(filter_method, bpp, &prev, &mut current);
-            zlib.write_all(&current)?;
-            prev = line;
-        }
+        let mut rows = RowFilter::new(&self.info);
+        rows.write_image(&mut zlib, width, height, self.info.interlaced, &data)?;
         let zlib_encoded = zlib.finish()?;
         for chunk in zlib_encoded.chunks(MAX_CHUNK_LEN as usize) {
-            self.write_chunk(chunk::IDAT, &chunk)?;
+            self.write_image_chunk(&chunk)?;
         }
         Ok(())
     }
 
+    /// Write the IEND chunk, finishing the image.
+    ///
+    /// Fails if the number of frames written does not match the animation control. Dropping the
+    /// writer also writes the IEND chunk but ignores any error.
+    pub fn finish(mut self) -> Result<()> {
+        if let Some(actl) = self.info.animation_control {
+            let frames_written = self.images_written.saturating_sub(self.sep_def_img as u32);
+            if frames_written != actl.num_frames {
+                let message = format!(
+                    "animation of {} frames only has {} frames written",
+                    actl.num_frames, frames_written
+                );
+                return Err(EncodingError::Format(message.into()));
+            }
+        }
+        self.write_trailer()
+    }
+
     /// Create an stream writer.
     ///
     /// This allows you create images that do not fit in memory. The default
@@ -309,7 +1189,9 @@ impl<W: Write> Writer<W> {
 
 impl<W: Write> Drop for Writer<W> {
     fn drop(&mut self) {
-        let _ = self.write_chunk(chunk::IEND, &[]);
+        if !self.iend_written {
+            let _ = self.write_trailer();
+        }
     }
 }
 
@@ -317,6 +1199,8 @@ struct ChunkWriter<'a, W: Write> {
     writer: ChunkOutput<'a, W>,
     buffer: Vec<u8>,
     index: usize,
+    /// Whether the frame control of the image has been written.
+    image_started: bool,
 }
 
 enum ChunkOutput<'a, W: Write> {
@@ -330,7 +1214,17 @@ impl<'a, W: Write> ChunkWriter<'a, W> {
             writer,
             buffer: vec![0; buf_len],
             index: 0,
+            image_started: false,
+        }
+    }
+
+    fn write_image_chunk(&mut self, len: usize) -> Result<()> {
+        let writer = self.writer.as_mut();
+        if !self.image_started {
+            writer.begin_image()?;
+            self.image_started = true;
         }
+        writer.write_image_chunk(&self.buffer[..len])
     }
 }
 
@@ -348,10 +1242,8 @@ impl<'a, W: Write> Write for ChunkWriter<'a, W> {
         let written = buf.read(&mut self.buffer[self.index..])?;
         self.index += written;
 
-        if self.index + 1 >= self.buffer.len() {
-            self.writer
-                .as_mut()
-                .write_chunk(chunk::IDAT, &self.buffer)?;
+        if self.index == self.buffer.len() {
+            self.write_image_chunk(self.index)?;
             self.index = 0;
         }
 
@@ -360,9 +1252,7 @@ impl<'a, W: Write> Write for ChunkWriter<'a, W> {
 
     fn flush(&mut self) -> io::Result<()> {
         if self.index > 0 {
-            self.writer
-                .as_mut()
-                .write_chunk(chunk::IDAT, &self.buffer[..=self.index])?;
+            self.write_image_chunk(self.index)?;
         }
         self.index = 0;
         Ok(())
@@ -375,39 +1265,146 @@ impl<'a, W: Write> Drop for ChunkWriter<'a, W> {
     }
 }
 
+/// Filters the rows of an image, or of one of its Adam7 passes, into the zlib stream.
+struct RowFilter {
+    method: FilterType,
+    adaptive: AdaptiveFilterType,
+    color_type: ColorType,
+    bit_depth: BitDepth,
+    bpp: BytesPerPixel,
+    /// The unfiltered previous row.
+    prev: Vec<u8>,
+    current: Vec<u8>,
+    /// Candidate rows of the adaptive filter.
+    scratch: Vec<u8>,
+}
+
+impl RowFilter {
+    fn new(info: &Info) -> RowFilter {
+        RowFilter {
+            method: info.filter,
+            adaptive: info.adaptive_filter,
+            color_type: info.color_type,
+            bit_depth: info.bit_depth,
+            bpp: info.bpp_in_prediction(),
+            prev: Vec::new(),
+            current: Vec::new(),
+            scratch: Vec::new(),
+        }
+    }
+
+    /// Start a new image with rows of `width` pixels, the previous row is all zeros.
+    fn reset(&mut self, width: u32) -> usize {
+        let len = self
+            .color_type
+            .raw_row_length_from_width(self.bit_depth, width)
+            - 1;
+        self.prev.clear();
+        self.prev.resize(len, 0);
+        self.current.resize(len, 0);
+        len
+    }
+
+    fn write_row<Z: Write>(&mut self, zlib: &mut Z, row: &[u8]) -> io::Result<()> {
+        self.current.copy_from_slice(row);
+        let method = match self.adaptive {
+            AdaptiveFilterType::NonAdaptive => {
+                filter(self.method, self.bpp, &self.prev, &mut self.current);
+                self.method
+            }
+            heuristic => filter_adaptive(
+                heuristic,
+                self.bpp,
+                &self.prev,
+                &mut self.current,
+                &mut self.scratch,
+            ),
+        };
+        zlib.write_all(&[method as u8])?;
+        zlib.write_all(&self.current)?;
+        self.prev.copy_from_slice(row);
+        Ok(())
+    }
+
+    /// Write all rows of an image, split into the seven Adam7 passes if it is interlaced.
+    fn write_image<Z: Write>(
+        &mut self,
+        zlib: &mut Z,
+        width: u32,
+        height: u32,
+        interlaced: bool,
+        data: &[u8],
+    ) -> io::Result<()> {
+        if !interlaced {
+            let in_len = self.reset(width);
+            for line in data.chunks(in_len) {
+                self.write_row(zlib, line)?;
+            }
+            return Ok(());
+        }
+
+        let bits_pp = self.color_type.samples() as u8 * self.bit_depth as u8;
+        let mut line = Vec::new();
+        for (pass, line_no, pass_width) in Adam7Iterator::new(width, height) {
+            if line_no == 0 {
+                let len = self.reset(pass_width);
+                line.resize(len, 0);
+            }
+            extract_pass_line(data, width, &mut line, pass, line_no, bits_pp);
+            self.write_row(zlib, &line)?;
+        }
+        Ok(())
+    }
+}
+
 /// Streaming png writer
 ///
 /// This may silently fail in the destructor, so it is a good idea to call
 /// [`finish`](#method.finish) or [`flush`](https://doc.rust-lang.org/stable/std/io/trait.Write.html#tymethod.flush) before dropping.
+///
+/// The image data of an interlaced image is buffered completely before it is written, as the
+/// first Adam7 pass already requires rows from the whole image.
 pub struct StreamWriter<'a, W: Write> {
     writer: deflate::write::ZlibEncoder<ChunkWriter<'a, W>>,
-    prev_buf: Vec<u8>,
+    rows: RowFilter,
     curr_buf: Vec<u8>,
     index: usize,
-    bpp: BytesPerPixel,
-    filter: FilterType,
+    /// The size of an interlaced image, `curr_buf` then holds the whole image.
+    interlaced_size: Option<(u32, u32)>,
+    /// Whether the header is missing as the writer is set up for lossless reduction.
+    header_missing: bool,
 }
 
 impl<'a, W: Write> StreamWriter<'a, W> {
     fn new(mut writer: ChunkOutput<'a, W>, buf_len: usize) -> StreamWriter<'a, W> {
-        let bpp = writer.as_mut().info.bpp_in_prediction();
-        let in_len = writer.as_mut().info.raw_row_length() - 1;
-        let filter = writer.as_mut().info.filter;
-        let prev_buf = vec![0; in_len];
-        let curr_buf = vec![0; in_len];
+        let rows = RowFilter::new(&writer.as_mut().info);
+        let header_missing = writer.as_mut().check_header_written().is_err();
+        let (width, height) = writer.as_mut().next_image_size();
+        let in_len = writer.as_mut().info.raw_row_length_from_width(width) - 1;
+        let interlaced_size = if writer.as_mut().info.interlaced {
+            Some((width, height))
+        } else {
+            None
+        };
+        let curr_buf = match interlaced_size {
+            Some(_) => vec![0; in_len * height as usize],
+            None => vec![0; in_len],
+        };
 
         let compression = writer.as_mut().info.compression.clone();
         let chunk_writer = ChunkWriter::new(writer, buf_len);
         let zlib = deflate::write::ZlibEncoder::new(chunk_writer, compression);
 
-        StreamWriter {
+        let mut stream_writer = StreamWriter {
             writer: zlib,
-            index: 0,
-            prev_buf,
+            rows,
             curr_buf,
-            bpp,
-            filter,
-        }
+            index: 0,
+            interlaced_size,
+            header_missing,
+        };
+        stream_writer.rows.reset(width);
+        stream_writer
     }
 
     pub fn finish(mut self) -> Result<()> {
@@ -419,14 +1416,21 @@ impl<'a, W: Write> StreamWriter<'a, W> {
 
 impl<'a, W: Write> Write for StreamWriter<'a, W> {
     fn write(&mut self, mut buf: &[u8]) -> io::Result<usize> {
+        if self.header_missing {
+            let message = "lossless reduction requires the image data to be written at once";
+            return Err(EncodingError::Format(message.into()).into());
+        }
         let written = buf.read(&mut self.curr_buf[self.index..])?;
         self.index += written;
 
         if self.index >= self.curr_buf.len() {
-            self.writer.write_all(&[self.filter as u8])?;
-            filter(self.filter, self.bpp, &self.prev_buf, &mut self.curr_buf);
-            self.writer.write_all(&self.curr_buf)?;
-            mem::swap(&mut self.prev_buf, &mut self.curr_buf);
+            match self.interlaced_size {
+                Some((width, height)) => {
+                    self.rows
+                        .write_image(&mut self.writer, width, height, true, &self.curr_buf)?;
+                }
+                None => self.rows.write_row(&mut self.writer, &self.curr_buf)?,
+            }
             self.index = 0;
         }
 
@@ -559,6 +1563,213 @@ mod tests {
         }
     }
 
+    #[test]
+    fn interlaced_roundtrip() {
+        for path in glob::glob("tests/pngsuite/bas*.png")
+            .unwrap()
+            .map(|r| r.unwrap())
+        {
+            let mut decoder = crate::Decoder::new(File::open(&path).unwrap());
+            decoder.set_transformations(crate::Transformations::IDENTITY);
+            let (info, mut reader) = decoder.read_info().unwrap();
+            let mut buf = vec![0; info.buffer_size()];
+            reader.next_frame(&mut buf).unwrap();
+            let source = reader.info();
+
+            for &stream in &[false, true] {
+                let mut out = Vec::new();
+                {
+                    let mut encoder = Encoder::new(&mut out, info.width, info.height);
+                    encoder.set_color(info.color_type);
+                    encoder.set_depth(info.bit_depth);
+                    encoder.set_filter(FilterType::Paeth);
+                    encoder.set_interlaced(true);
+                    if let Some(palette) = &source.palette {
+                        encoder.set_palette(palette.clone());
+                    }
+                    let mut writer = encoder.write_header().unwrap();
+                    if stream {
+                        let mut stream_writer = writer.stream_writer_with_size(7);
+                        for row in buf.chunks(5) {
+                            stream_writer.write_all(row).unwrap();
+                        }
+                        stream_writer.finish().unwrap();
+                    } else {
+                        writer.write_image_data(&buf).unwrap();
+                    }
+                }
+
+                let mut decoder = crate::Decoder::new(&*out);
+                decoder.set_transformations(crate::Transformations::IDENTITY);
+                let (info, mut reader) = decoder.read_info().unwrap();
+                assert!(reader.info().interlaced);
+                let mut buf2 = vec![0; info.buffer_size()];
+                reader.next_frame(&mut buf2).unwrap();
+                assert_eq!(buf, buf2, "{} (stream: {})", path.display(), stream);
+            }
+        }
+    }
+
+    #[test]
+    fn adaptive_filter_roundtrip() {
+        let heuristics = [
+            AdaptiveFilterType::MinSum,
+            AdaptiveFilterType::Entropy,
+            AdaptiveFilterType::BruteForce,
+        ];
+        for name in &["basn0g08", "basn2c16", "basn3p04", "basi6a08"] {
+            let path = format!("tests/pngsuite/{}.png", name);
+            let mut decoder = crate::Decoder::new(File::open(&path).unwrap());
+            decoder.set_transformations(crate::Transformations::IDENTITY);
+            let (info, mut reader) = decoder.read_info().unwrap();
+            let mut buf = vec![0; info.buffer_size()];
+            reader.next_frame(&mut buf).unwrap();
+
+            for &heuristic in heuristics.iter() {
+                for &stream in &[false, true] {
+                    let mut out = Vec::new();
+                    {
+                        let mut encoder = Encoder::new(&mut out, info.width, info.height);
+                        encoder.set_color(info.color_type);
+                        encoder.set_depth(info.bit_depth);
+                        encoder.set_interlaced(reader.info().interlaced);
+                        encoder.set_adaptive_filter(heuristic);
+                        if let Some(palette) = &reader.info().palette {
+                            encoder.set_palette(palette.clone());
+                        }
+                        let mut writer = encoder.write_header().unwrap();
+                        if stream {
+                            let mut stream_writer = writer.stream_writer();
+                            stream_writer.write_all(&buf).unwrap();
+                            stream_writer.finish().unwrap();
+                        } else {
+                            writer.write_image_data(&buf).unwrap();
+                        }
+                    }
+
+                    let mut decoder = crate::Decoder::new(&*out);
+                    decoder.set_transformations(crate::Transformations::IDENTITY);
+                    let (info, mut reader) = decoder.read_info().unwrap();
+                    let mut buf2 = vec![0; info.buffer_size()];
+                    reader.next_frame(&mut buf2).unwrap();
+                    assert_eq!(buf, buf2, "{} with {:?}", path, heuristic);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn lossless_reduction_roundtrip() {
+        fn decode_rgba16(png: &[u8]) -> (Info, Vec<[u16; 4]>) {
+            let mut decoder = crate::Decoder::new(png);
+            decoder.set_transformations(crate::Transformations::EXPAND);
+            let (info, mut reader) = decoder.read_info().unwrap();
+            let mut buf = vec![0; info.buffer_size()];
+            reader.next_frame(&mut buf).unwrap();
+            let (color_type, bit_depth) = reader.output_color_type();
+            let samples: Vec<u16> = match bit_depth {
+                BitDepth::Sixteen => buf
+                    .chunks(2)
+                    .map(|s| u16::from_be_bytes([s[0], s[1]]))
+                    .collect(),
+                _ => buf.iter().map(|&s| u16::from(s) * 257).collect(),
+            };
+            let pixels = samples
+                .chunks(color_type.samples())
+                .map(|p| match color_type {
+                    ColorType::Grayscale => [p[0], p[0], p[0], 0xFFFF],
+                    ColorType::GrayscaleAlpha => [p[0], p[0], p[0], p[1]],
+                    ColorType::RGB => [p[0], p[1], p[2], 0xFFFF],
+                    _ => [p[0], p[1], p[2], p[3]],
+                })
+                .collect();
+            (reader.info().clone(), pixels)
+        }
+
+        fn encode_reduced(info: &Info, data: &[u8]) -> Vec<u8> {
+            let mut out = Vec::new();
+            {
+                let mut encoder = Encoder::new(&mut out, info.width, info.height);
+                encoder.set_color(info.color_type);
+                encoder.set_depth(info.bit_depth);
+                encoder.set_lossless_reduction(true);
+                if let Some(palette) = &info.palette {
+                    encoder.set_palette(palette.clone());
+                }
+                if let Some(trns) = &info.trns {
+                    encoder.set_trns(trns.clone());
+                }
+                let mut writer = encoder.write_header().unwrap();
+                writer.write_image_data(data).unwrap();
+            }
+            out
+        }
+
+        for path in glob::glob("tests/pngsuite/[bt]*.png")
+            .unwrap()
+            .map(|r| r.unwrap())
+        {
+            let original = std::fs::read(&path).unwrap();
+            let mut decoder = crate::Decoder::new(&*original);
+            decoder.set_transformations(crate::Transformations::IDENTITY);
+            let (info, mut reader) = decoder.read_info().unwrap();
+            let mut buf = vec![0; info.buffer_size()];
+            reader.next_frame(&mut buf).unwrap();
+            let (expected_info, expected) = decode_rgba16(&original);
+            let bits = |info: &Info| info.bits_per_pixel();
+
+            // The decoder does not keep the tRNS chunk of truecolor images as is, reduction does
+            // not create one either.
+            let color_key = reader.info().trns.is_some() && info.color_type != ColorType::Indexed;
+            if !color_key {
+                let reduced_png = encode_reduced(reader.info(), &buf);
+                let (reduced_info, reduced) = decode_rgba16(&reduced_png);
+                assert_eq!(expected, reduced, "{}", path.display());
+                assert!(bits(&reduced_info) <= bits(&expected_info));
+            }
+
+            // Reduce the image from RGBA with 16 bits per sample.
+            let rgba16: Vec<u8> = expected
+                .iter()
+                .flat_map(|p| p.iter().flat_map(|s| s.to_be_bytes().to_vec()))
+                .collect();
+            let source = Info {
+                width: info.width,
+                height: info.height,
+                color_type: ColorType::RGBA,
+                bit_depth: BitDepth::Sixteen,
+                ..Info::default()
+            };
+            let (reduced_info, reduced) = decode_rgba16(&encode_reduced(&source, &rgba16));
+            assert_eq!(expected, reduced, "{}", path.display());
+            assert!(
+                color_key || bits(&reduced_info) <= bits(&expected_info),
+                "{} {:?} {:?}",
+                path.display(),
+                reduced_info.color_type,
+                reduced_info.bit_depth
+            );
+        }
+    }
+
+    #[test]
+    fn expect_error_on_chunks_before_reduced_data() -> Result<()> {
+        let mut encoder = Encoder::new(Vec::new(), 1, 1);
+        encoder.set_lossless_reduction(true);
+        let mut writer = encoder.write_header()?;
+        assert!(writer.write_chunk(*b"teST", &[]).is_err());
+        assert!(writer.stream_writer().write(&[0]).is_err());
+        writer.write_image_data(&[0])?;
+        writer.write_chunk(*b"teST", &[])?;
+        writer.finish()?;
+
+        let mut encoder = Encoder::new(Vec::new(), 1, 1);
+        encoder.set_lossless_reduction(true);
+        encoder.set_animated(1, 0)?;
+        assert!(encoder.write_header().is_err());
+        Ok(())
+    }
+
     #[test]
     fn image_palette() -> Result<()> {
         let samples = 3;
@@ -882,6 +2093,699 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn text_chunks_roundtrip() -> Result<()> {
+        let mut provenance = ITXtChunk::new("Source", "Ümlaut ∑ provenance");
+        provenance.compressed = true;
+        provenance.language_tag = "de".to_string();
+        provenance.translated_keyword = "Quelle".to_string();
+
+        let mut buffer = vec![];
+        {
+            let mut encoder = Encoder::new(&mut buffer, 1, 1);
+            encoder.add_text_chunk("Title".to_string(), "Pixel".to_string())?;
+            encoder.add_ztxt_chunk("Comment".to_string(), "Café ".repeat(100))?;
+            encoder.add_itxt_chunk("Author".to_string(), "Ω".to_string())?;
+            let mut writer = encoder.write_header()?;
+            writer.write_image_data(&[0])?;
+            writer.write_text_chunk(&provenance)?;
+        }
+
+        let decoder = crate::Decoder::new(&*buffer);
+        let (_, mut reader) = decoder.read_info().unwrap();
+        let info = reader.info();
+        assert_eq!(
+            info.uncompressed_latin1_text,
+            vec![TEXtChunk::new("Title", "Pixel")]
+        );
+        assert_eq!(
+            info.compressed_latin1_text,
+            vec![ZTXtChunk::new("Comment", "Café ".repeat(100))]
+        );
+        assert_eq!(info.utf8_text, vec![ITXtChunk::new("Author", "Ω")]);
+
+        reader.finish().unwrap();
+        assert_eq!(reader.info().utf8_text[1], provenance);
+        Ok(())
+    }
+
+    #[test]
+    fn expect_error_on_invalid_text_chunks() {
+        let mut encoder = Encoder::new(Vec::new(), 1, 1);
+        assert!(encoder
+            .add_text_chunk("".to_string(), "text".to_string())
+            .is_err());
+        assert!(encoder
+            .add_text_chunk("k".repeat(80), "text".to_string())
+            .is_err());
+        assert!(encoder
+            .add_text_chunk(" Title".to_string(), "text".to_string())
+            .is_err());
+        assert!(encoder
+            .add_text_chunk("Two  spaces".to_string(), "text".to_string())
+            .is_err());
+        assert!(encoder
+            .add_text_chunk("Title\n".to_string(), "text".to_string())
+            .is_err());
+        assert!(encoder
+            .add_ztxt_chunk("Title".to_string(), "∑".to_string())
+            .is_err());
+        assert!(encoder
+            .add_itxt_chunk("∑".to_string(), "∑".to_string())
+            .is_err());
+        assert!(encoder
+            .add_text_chunk("Title".to_string(), "null\0byte".to_string())
+            .is_err());
+        assert!(encoder
+            .add_ztxt_chunk("Title".to_string(), "null\0byte".to_string())
+            .is_err());
+        let mut writer = Encoder::new(Vec::new(), 1, 1).write_header().unwrap();
+        assert!(writer
+            .write_text_chunk(&TEXtChunk::new("Title", "null\0byte"))
+            .is_err());
+        assert!(writer
+            .write_text_chunk(&ZTXtChunk::new("Title", "null\0byte"))
+            .is_err());
+        assert!(encoder
+            .add_text_chunk("Título".to_string(), "text".to_string())
+            .is_ok());
+    }
+
+    #[test]
+    fn icc_profile_roundtrip() -> Result<()> {
+        let profile: Vec<u8> = (0..4096).map(|i| (i % 251) as u8).collect();
+        let mut buffer = vec![];
+        {
+            let mut encoder = Encoder::new(&mut buffer, 1, 1);
+            encoder.set_color(ColorType::RGB);
+            encoder.set_icc_profile("Display P3".to_string(), profile.clone())?;
+            let mut writer = encoder.write_header()?;
+            writer.write_image_data(&[0, 0, 0])?;
+            assert!(writer.write_chunk(chunk::sRGB, &[0]).is_err());
+        }
+
+        let (_, reader) = crate::Decoder::new(&*buffer).read_info().unwrap();
+        let icc = reader.info().icc_profile.as_ref().unwrap();
+        assert_eq!(icc.name, "Display P3");
+        assert_eq!(icc.profile, profile);
+
+        let limits = crate::Limits { bytes: 1024 };
+        match crate::Decoder::new_with_limits(&*buffer, limits).read_info() {
+            Err(crate::DecodingError::LimitsExceeded) => {}
+            Err(err) => panic!("unexpected error {}", err),
+            Ok(_) => panic!("ICC profile exceeding the limit was decoded"),
+        }
+
+        let mut encoder = Encoder::new(Vec::new(), 1, 1);
+        assert!(encoder.set_icc_profile("".to_string(), profile).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn srgb_roundtrip() -> Result<()> {
+        let mut buffer = vec![];
+        {
+            let mut encoder = Encoder::new(&mut buffer, 1, 1);
+            encoder.set_srgb_with_fallbacks(SrgbRenderingIntent::Saturation)?;
+            assert!(encoder
+                .set_icc_profile("Display P3".to_string(), vec![])
+                .is_err());
+            let mut writer = encoder.write_header()?;
+            writer.write_image_data(&[0])?;
+            assert!(writer.write_chunk(chunk::iCCP, &[0]).is_err());
+        }
+
+        let (_, reader) = crate::Decoder::new(&*buffer).read_info().unwrap();
+        let info = reader.info();
+        assert_eq!(info.srgb, Some(SrgbRenderingIntent::Saturation));
+        assert_eq!(info.source_gamma, Some(ScaledFloat::new(0.45455)));
+        assert_eq!(
+            info.source_chromaticities,
+            Some(SourceChromaticities::new(
+                (0.3127, 0.3290),
+                (0.64, 0.33),
+                (0.30, 0.60),
+                (0.15, 0.06),
+            ))
+        );
+
+        let mut encoder = Encoder::new(Vec::new(), 1, 1);
+        encoder.set_icc_profile("Display P3".to_string(), vec![])?;
+        assert!(encoder.set_srgb(SrgbRenderingIntent::Perceptual).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn significant_bits_roundtrip() -> Result<()> {
+        // 12-bit samples scaled to 16 bits by replicating their high bits.
+        let samples: Vec<u16> = (0..12).map(|i| i * 341).collect();
+        let data: Vec<u8> = samples
+            .iter()
+            .flat_map(|&s| ((s << 4) | (s >> 8)).to_be_bytes().to_vec())
+            .collect();
+        let mut buffer = vec![];
+        {
+            let mut encoder = Encoder::new(&mut buffer, 2, 2);
+            encoder.set_color(ColorType::RGB);
+            encoder.set_depth(BitDepth::Sixteen);
+            encoder.set_significant_bits(vec![12, 12, 12]);
+            encoder.set_lossless_reduction(true);
+            let mut writer = encoder.write_header()?;
+            writer.write_image_data(&data)?;
+        }
+
+        let mut decoder = crate::Decoder::new(&*buffer);
+        decoder.set_transformations(crate::Transformations::SHIFT);
+        let (info, mut reader) = decoder.read_info().unwrap();
+        assert_eq!(info.bit_depth, BitDepth::Sixteen);
+        assert_eq!(reader.info().significant_bits, Some(vec![12, 12, 12]));
+        let mut buf = vec![0; info.buffer_size()];
+        reader.next_frame(&mut buf).unwrap();
+        let decoded: Vec<u16> = buf
+            .chunks(2)
+            .map(|s| u16::from_be_bytes([s[0], s[1]]))
+            .collect();
+        assert_eq!(decoded, samples);
+
+        for &bits in [&[12, 12][..], &[0, 8, 8], &[8, 17, 8]].iter() {
+            let mut encoder = Encoder::new(Vec::new(), 1, 1);
+            encoder.set_color(ColorType::RGB);
+            encoder.set_depth(BitDepth::Sixteen);
+            encoder.set_significant_bits(bits.to_vec());
+            assert!(encoder.write_header().is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn background_color_roundtrip() -> Result<()> {
+        let colors = [
+            (ColorType::Indexed, BackgroundColor::Indexed(1)),
+            (ColorType::GrayscaleAlpha, BackgroundColor::Gray(0x1234)),
+            (ColorType::RGB, BackgroundColor::Rgb(1, 0xFFFF, 3)),
+        ];
+        for &(color_type, color) in colors.iter() {
+            let mut buffer = vec![];
+            {
+                let mut encoder = Encoder::new(&mut buffer, 1, 1);
+                encoder.set_color(color_type);
+                if color_type == ColorType::Indexed {
+                    encoder.set_palette(vec![0; 6]);
+                } else {
+                    encoder.set_depth(BitDepth::Sixteen);
+                }
+                encoder.set_background_color(color);
+                encoder.set_lossless_reduction(true);
+                let mut writer = encoder.write_header()?;
+                let len = match color_type {
+                    ColorType::Indexed => 1,
+                    _ => color_type.samples() * 2,
+                };
+                writer.write_image_data(&vec![0; len])?;
+            }
+            let decoder = crate::Decoder::new(&*buffer);
+            let (_, reader) = decoder.read_info().unwrap();
+            assert_eq!(reader.info().background_color, Some(color));
+        }
+
+        let invalid = [
+            (ColorType::Indexed, BackgroundColor::Indexed(2)),
+            (ColorType::Grayscale, BackgroundColor::Gray(0x100)),
+            (ColorType::RGBA, BackgroundColor::Gray(0)),
+        ];
+        for &(color_type, color) in invalid.iter() {
+            let mut encoder = Encoder::new(Vec::new(), 1, 1);
+            encoder.set_color(color_type);
+            encoder.set_palette(vec![0; 6]);
+            encoder.set_background_color(color);
+            assert!(encoder.write_header().is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn exif_orientation_roundtrip() -> Result<()> {
+        // A little endian TIFF header with an orientation entry in the first directory.
+        let exif = |orientation: u8| {
+            let mut exif = b"II*\0\x08\0\0\0\x02\0".to_vec();
+            exif.extend_from_slice(&[0x0F, 0x01, 2, 0, 1, 0, 0, 0, 0, 0, 0, 0]);
+            exif.extend_from_slice(&[0x12, 0x01, 3, 0, 1, 0, 0, 0, orientation, 0, 0, 0]);
+            exif.extend_from_slice(&[0; 4]);
+            exif
+        };
+        let encode = |exif: Vec<u8>| -> Result<Vec<u8>> {
+            let mut buffer = vec![];
+            {
+                let mut encoder = Encoder::new(&mut buffer, 3, 2);
+                encoder.set_exif_metadata(exif);
+                let mut writer = encoder.write_header()?;
+                writer.write_image_data(&[1, 2, 3, 4, 5, 6])?;
+            }
+            Ok(buffer)
+        };
+
+        let buffer = encode(exif(6))?;
+        let mut decoder = crate::Decoder::new(&*buffer);
+        decoder.set_transformations(crate::Transformations::ORIENTATION);
+        let (info, mut reader) = decoder.read_info().unwrap();
+        assert_eq!(reader.info().exif_metadata, Some(exif(6)));
+        assert_eq!(
+            reader.info().orientation(),
+            Some(crate::Orientation::RightTop)
+        );
+        assert_eq!((info.width, info.height, info.line_size), (2, 3, 2));
+        let mut buf = vec![0; info.buffer_size()];
+        reader.next_frame(&mut buf).unwrap();
+        assert_eq!(buf, [4, 1, 5, 2, 6, 3]);
+
+        // Big endian data and the prefix of JPEG files.
+        let mut big_endian = b"Exif\0\0MM\0*\0\0\0\x08\0\x01".to_vec();
+        big_endian.extend_from_slice(&[0x01, 0x12, 0, 3, 0, 0, 0, 1, 0, 3, 0, 0]);
+        let info = Info {
+            exif_metadata: Some(big_endian),
+            ..Info::default()
+        };
+        assert_eq!(info.orientation(), Some(crate::Orientation::BottomRight));
+
+        let buffer = encode(exif(9))?;
+        let (_, reader) = crate::Decoder::new(&*buffer).read_info().unwrap();
+        assert_eq!(reader.info().orientation(), None);
+        assert!(encode(b"Exif\0\0".to_vec()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn palette_auxiliary_roundtrip() -> Result<()> {
+        let entry = |value: u16| SuggestedPaletteEntry {
+            red: value,
+            green: value,
+            blue: 0,
+            alpha: 0xFF,
+            frequency: value,
+        };
+        let palettes = vec![
+            SuggestedPalette {
+                name: "gray".to_string(),
+                sample_depth: BitDepth::Eight,
+                entries: vec![entry(0), entry(0x80)],
+            },
+            SuggestedPalette {
+                name: "deep".to_string(),
+                sample_depth: BitDepth::Sixteen,
+                entries: vec![entry(0x1234)],
+            },
+        ];
+        let mut buffer = vec![];
+        {
+            let mut encoder = Encoder::new(&mut buffer, 1, 1);
+            encoder.set_color(ColorType::Indexed);
+            encoder.set_palette(vec![0; 6]);
+            encoder.set_histogram(vec![3, 0]);
+            for palette in palettes.iter().cloned() {
+                encoder.add_suggested_palette(palette)?;
+            }
+            assert!(encoder.add_suggested_palette(palettes[0].clone()).is_err());
+            encoder.set_lossless_reduction(true);
+            let mut writer = encoder.write_header()?;
+            writer.write_image_data(&[0])?;
+        }
+        let decoder = crate::Decoder::new(&*buffer);
+        let (_, reader) = decoder.read_info().unwrap();
+        assert_eq!(reader.info().histogram, Some(vec![3, 0]));
+        assert_eq!(reader.info().suggested_palettes, palettes);
+
+        let mut encoder = Encoder::new(Vec::new(), 1, 1);
+        assert!(encoder
+            .add_suggested_palette(SuggestedPalette {
+                entries: vec![entry(0x100)],
+                ..palettes[0].clone()
+            })
+            .is_err());
+        encoder.set_color(ColorType::Indexed);
+        encoder.set_palette(vec![0; 6]);
+        encoder.set_histogram(vec![1]);
+        assert!(encoder.write_header().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_chunks_roundtrip() -> Result<()> {
+        let unknown = |name: &[u8; 4], len: usize, position| UnknownChunk {
+            chunk_type: *name,
+            data: (0..len).map(|i| i as u8).collect(),
+            position,
+        };
+        // Lowercase last letters are safe to copy, the large chunk spans several decoder buffers.
+        let chunks = vec![
+            unknown(b"prVt", 3, ChunkPosition::BeforePlte),
+            unknown(b"prVT", 40_000, ChunkPosition::BeforeIdat),
+            unknown(b"prVt", 0, ChunkPosition::AfterIdat),
+        ];
+        let encode = |chunks: &[UnknownChunk], modified: bool| -> Result<Vec<u8>> {
+            let mut buffer = vec![];
+            {
+                let mut encoder = Encoder::new(&mut buffer, 1, 1);
+                encoder.set_color(ColorType::Indexed);
+                encoder.set_palette(vec![0; 3]);
+                encoder.set_image_modified(modified);
+                for chunk in chunks.iter().cloned() {
+                    encoder.add_unknown_chunk(chunk)?;
+                }
+                let mut writer = encoder.write_header()?;
+                writer.write_image_data(&[0])?;
+            }
+            Ok(buffer)
+        };
+        let decode = |buffer: &[u8], preserve: bool| {
+            let mut decoder = crate::Decoder::new(buffer);
+            decoder.set_preserve_unknown_chunks(preserve);
+            let (_, mut reader) = decoder.read_info().unwrap();
+            reader.finish().unwrap();
+            reader.info().unknown_chunks.clone()
+        };
+
+        let buffer = encode(&chunks, false)?;
+        assert_eq!(decode(&buffer, false), vec![]);
+        let decoded = decode(&buffer, true);
+        assert_eq!(decoded, chunks);
+
+        // Chunks that are not kept are streamed, whatever their size.
+        let limits = crate::Limits { bytes: 20_000 };
+        let decoder = crate::Decoder::new_with_limits(&buffer[..], limits);
+        let (_, mut reader) = decoder.read_info().unwrap();
+        reader.finish().unwrap();
+        let mut decoder = crate::Decoder::new_with_limits(&buffer[..], limits);
+        decoder.set_preserve_unknown_chunks(true);
+        assert!(decoder.read_info().is_err());
+
+        let buffer = encode(&decoded, true)?;
+        let safe = vec![chunks[0].clone(), chunks[2].clone()];
+        assert_eq!(decode(&buffer, true), safe);
+
+        let mut encoder = Encoder::new(Vec::new(), 1, 1);
+        for name in [b"IDAT", b"prvt", b"pr1t"].iter() {
+            assert!(encoder
+                .add_unknown_chunk(unknown(name, 0, ChunkPosition::AfterIdat))
+                .is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn extension_chunks_roundtrip() -> Result<()> {
+        use crate::{EquationType, OffsetUnit, ScaleUnit};
+        let offset = ImageOffset {
+            x: -20,
+            y: 300,
+            unit: OffsetUnit::Micrometer,
+        };
+        // Elevation in meters of 0.1 m per sample from -500 m.
+        let calibration = PixelCalibration {
+            name: "elevation".to_string(),
+            original_zero: 0,
+            original_max: 0xFFFF,
+            equation_type: EquationType::Linear,
+            unit: "m".to_string(),
+            parameters: vec![-500.0, 6553.5],
+        };
+        let scale = PhysicalScale {
+            unit: ScaleUnit::Meter,
+            pixel_width: 0.5,
+            pixel_height: 1e-7,
+        };
+        let mut buffer = vec![];
+        {
+            let mut encoder = Encoder::new(&mut buffer, 1, 1);
+            encoder.set_color(ColorType::Grayscale);
+            encoder.set_depth(BitDepth::Sixteen);
+            encoder.set_offset(offset);
+            encoder.set_pixel_calibration(calibration.clone())?;
+            encoder.set_physical_scale(scale)?;
+            encoder.set_stereo_layout(StereoLayout::DivergingFuse);
+            let mut writer = encoder.write_header()?;
+            writer.write_image_data(&[0, 0])?;
+        }
+        let decoder = crate::Decoder::new(&*buffer);
+        let (_, reader) = decoder.read_info().unwrap();
+        let info = reader.info();
+        assert_eq!(info.offset, Some(offset));
+        assert_eq!(info.pixel_calibration, Some(calibration.clone()));
+        assert_eq!(info.physical_scale, Some(scale));
+        assert_eq!(info.stereo_layout, Some(StereoLayout::DivergingFuse));
+        let elevation = calibration.physical_value(1000, 0xFFFF).unwrap();
+        assert!((elevation + 400.0).abs() < 1e-9);
+
+        let mut encoder = Encoder::new(Vec::new(), 1, 1);
+        assert!(encoder
+            .set_pixel_calibration(PixelCalibration {
+                parameters: vec![0.0, 1.0, 2.0],
+                ..calibration.clone()
+            })
+            .is_err());
+        assert!(encoder
+            .set_pixel_calibration(PixelCalibration {
+                original_max: 0,
+                ..calibration
+            })
+            .is_err());
+        assert!(encoder
+            .set_physical_scale(PhysicalScale {
+                pixel_width: 0.0,
+                ..scale
+            })
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn hdr_metadata_roundtrip() -> Result<()> {
+        // BT.2020 primaries with the PQ transfer function.
+        let cicp = CodingIndependentCodePoints {
+            color_primaries: 9,
+            transfer_function: 16,
+            matrix_coefficients: 0,
+            is_video_full_range_image: true,
+        };
+        let mdcv = MasteringDisplayColorVolume {
+            white: (15635, 16450),
+            red: (35400, 14600),
+            green: (8500, 39850),
+            blue: (6550, 2300),
+            max_luminance: 10_000_000,
+            min_luminance: 1,
+        };
+        let clli = ContentLightLevelInfo {
+            max_content_light_level: 4_000_000,
+            max_frame_average_light_level: 1_000_000,
+        };
+        let mut buffer = vec![];
+        {
+            let mut encoder = Encoder::new(&mut buffer, 1, 1);
+            encoder.set_color(ColorType::RGB);
+            encoder.set_depth(BitDepth::Sixteen);
+            encoder.set_coding_independent_code_points(cicp);
+            encoder.set_mastering_display_color_volume(mdcv);
+            encoder.set_content_light_level(clli);
+            let mut writer = encoder.write_header()?;
+            writer.write_image_data(&[0; 6])?;
+            assert!(writer.write_chunk(chunk::cLLi, &[0; 8]).is_err());
+        }
+        let decoder = crate::Decoder::new(&*buffer);
+        let (_, reader) = decoder.read_info().unwrap();
+        let info = reader.info();
+        assert_eq!(info.coding_independent_code_points, Some(cicp));
+        assert_eq!(info.mastering_display_color_volume, Some(mdcv));
+        assert_eq!(info.content_light_level, Some(clli));
+
+        let mut encoder = Encoder::new(Vec::new(), 1, 1);
+        encoder.set_coding_independent_code_points(CodingIndependentCodePoints {
+            matrix_coefficients: 1,
+            ..cicp
+        });
+        assert!(encoder.write_header().is_err());
+
+        // A cICP chunk following the palette is rejected by both sides.
+        let mut encoder = Encoder::new(Vec::new(), 1, 1);
+        encoder.set_color(ColorType::Indexed);
+        encoder.set_palette(vec![0; 3]);
+        let mut writer = encoder.write_header()?;
+        assert!(writer.write_chunk(chunk::cICP, &[9, 16, 0, 1]).is_err());
+        let mut png = vec![137, 80, 78, 71, 13, 10, 26, 10];
+        write_chunk(
+            &mut png,
+            chunk::IHDR,
+            &[0, 0, 0, 1, 0, 0, 0, 1, 8, 3, 0, 0, 0],
+        )?;
+        write_chunk(&mut png, chunk::PLTE, &[0; 3])?;
+        write_chunk(&mut png, chunk::cICP, &[9, 16, 0, 1])?;
+        match crate::Decoder::new(&*png).read_info() {
+            Err(err) => assert!(err.to_string().contains("cICP")),
+            Ok(_) => panic!("cICP after PLTE was accepted"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn pixel_dims_roundtrip() -> Result<()> {
+        let pixel_dims = PixelDimensions::from_dpi(300.0, 72.0);
+        assert_eq!((pixel_dims.xppu, pixel_dims.yppu), (11811, 2835));
+        let (x_dpi, y_dpi) = pixel_dims.dpi().unwrap();
+        assert_eq!((x_dpi.round(), y_dpi.round()), (300.0, 72.0));
+        assert_eq!(PixelDimensions::from_dpi(-1.0, 1e20).xppu, 0);
+        assert_eq!(
+            PixelDimensions::from_dpi(-1.0, 1e20).yppu,
+            i32::max_value() as u32
+        );
+
+        let mut buffer = vec![];
+        {
+            let mut encoder = Encoder::new(&mut buffer, 1, 1);
+            encoder.set_pixel_dims(pixel_dims);
+            let mut writer = encoder.write_header()?;
+            writer.write_image_data(&[0])?;
+        }
+        let decoder = crate::Decoder::new(&*buffer);
+        let (_, reader) = decoder.read_info().unwrap();
+        assert_eq!(reader.info().pixel_dims, Some(pixel_dims));
+
+        let mut encoder = Encoder::new(Vec::new(), 1, 1);
+        encoder.set_pixel_dims(PixelDimensions {
+            xppu: 1,
+            yppu: 1 << 31,
+            unit: crate::Unit::Meter,
+        });
+        assert!(encoder.write_header().is_err());
+
+        let aspect_ratio = PixelDimensions {
+            xppu: 2,
+            yppu: 1,
+            unit: crate::Unit::Unspecified,
+        };
+        assert_eq!(aspect_ratio.dpi(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn last_modified_roundtrip() -> Result<()> {
+        use std::time::{Duration, UNIX_EPOCH};
+        let at = |seconds| Time::from_system_time(UNIX_EPOCH + Duration::from_secs(seconds));
+        assert_eq!(at(0), Time::new(1970, 1, 1, 0, 0, 0));
+        assert_eq!(at(951_782_400), Time::new(2000, 2, 29, 0, 0, 0));
+        assert_eq!(at(4_107_542_399), Time::new(2100, 2, 28, 23, 59, 59));
+        assert_eq!(at(1_700_000_000), Time::new(2023, 11, 14, 22, 13, 20));
+        assert_eq!(
+            Time::from_system_time(UNIX_EPOCH - Duration::from_secs(1)),
+            None
+        );
+        assert_eq!(Time::new(2100, 2, 29, 0, 0, 0), None);
+        assert_eq!(Time::new(2000, 13, 1, 0, 0, 0), None);
+
+        let time = at(1_700_000_000).unwrap();
+        let mut buffer = vec![];
+        {
+            let mut encoder = Encoder::new(&mut buffer, 1, 1);
+            encoder.set_last_modified(time);
+            let mut writer = encoder.write_header()?;
+            writer.write_image_data(&[0])?;
+        }
+        let decoder = crate::Decoder::new(&*buffer);
+        let (_, reader) = decoder.read_info().unwrap();
+        assert_eq!(reader.info().last_modified, Some(time));
+
+        let mut encoder = Encoder::new(Vec::new(), 1, 1);
+        encoder.set_last_modified(Time { hour: 24, ..time });
+        assert!(encoder.write_header().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn animation_roundtrip() -> Result<()> {
+        let mut out = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut out, 4, 2);
+            encoder.set_depth(BitDepth::Eight);
+            encoder.set_color(ColorType::Grayscale);
+            encoder.set_animated(3, 0)?;
+            encoder.set_sep_def_img(true)?;
+            encoder.set_frame_delay(1, 10)?;
+            let mut writer = encoder.write_header()?;
+            writer.write_image_data(&[255; 8])?;
+            writer.write_image_data(&[10; 8])?;
+
+            writer.set_frame_dimension(2, 1)?;
+            writer.set_frame_position(1, 1)?;
+            writer.set_frame_delay(2, 0)?;
+            writer.set_blend_op(BlendOp::Over)?;
+            writer.set_dispose_op(DisposeOp::Background)?;
+            let mut stream_writer = writer.stream_writer_with_size(1);
+            stream_writer.write_all(&[20, 30])?;
+            stream_writer.finish()?;
+
+            writer.reset_frame_dimension()?;
+            writer.reset_frame_position()?;
+            writer.write_image_data(&[40; 8])?;
+            writer.finish()?;
+        }
+
+        let decoder = crate::Decoder::new(&*out);
+        let (info, mut reader) = decoder.read_info().unwrap();
+        assert_eq!(reader.info().animation_control().unwrap().num_frames, 3);
+        let mut default_image = vec![0; info.buffer_size()];
+        reader.next_frame(&mut default_image).unwrap();
+        assert_eq!(default_image, [255; 8]);
+        assert!(reader.info().frame_control.is_none());
+
+        let decoder = crate::Decoder::new(&*out);
+        let (_, reader) = decoder.read_info().unwrap();
+        let mut animation = crate::AnimationDecoder::new(reader).unwrap();
+        let mut frame = vec![0; animation.output_buffer_size()];
+        let gray = |v: u8| [v, v, v, 255];
+
+        let delay = animation.next_frame(&mut frame).unwrap().unwrap();
+        assert_eq!((delay.numerator, delay.denominator), (1, 10));
+        assert!(frame.chunks(4).all(|pixel| pixel == gray(10)));
+
+        let delay = animation.next_frame(&mut frame).unwrap().unwrap();
+        assert_eq!((delay.numerator, delay.denominator), (2, 0));
+        let expected: Vec<u8> = [10, 10, 10, 10, 10, 20, 30, 10]
+            .iter()
+            .flat_map(|&v| gray(v).to_vec())
+            .collect();
+        assert_eq!(frame, expected);
+
+        animation.next_frame(&mut frame).unwrap().unwrap();
+        assert!(frame.chunks(4).all(|pixel| pixel == gray(40)));
+        assert_eq!(animation.next_frame(&mut frame).unwrap(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn expect_error_on_invalid_animation() -> Result<()> {
+        let mut encoder = Encoder::new(Vec::new(), 2, 2);
+        assert!(encoder.set_frame_delay(1, 1).is_err());
+        assert!(encoder.set_sep_def_img(true).is_err());
+        assert!(encoder.set_animated(0, 0).is_err());
+
+        let mut encoder = Encoder::new(Vec::new(), 2, 2);
+        encoder.set_animated(2, 0)?;
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&[0; 4])?;
+        writer.set_frame_dimension(2, 1)?;
+        writer.set_frame_position(0, 2)?;
+        assert!(writer.write_image_data(&[0; 2]).is_err());
+        assert!(writer.finish().is_err());
+
+        let mut encoder = Encoder::new(Vec::new(), 2, 2);
+        encoder.set_animated(1, 0)?;
+        let mut writer = encoder.write_header()?;
+        writer.set_frame_dimension(1, 1)?;
+        assert!(writer.write_image_data(&[0; 1]).is_err());
+        writer.reset_frame_dimension()?;
+        writer.write_image_data(&[0; 4])?;
+        assert!(writer.write_image_data(&[0; 4]).is_err());
+        writer.finish()
+    }
+
     /// A Writer that only writes a few bytes at a time
     struct RandomChunkWriter<'a, R: Rng, W: Write + 'a> {
         rng: R,
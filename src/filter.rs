@@ -16,6 +16,24 @@ pub enum FilterType {
     Paeth = 4,
 }
 
+/// The heuristic used to choose the filter of each scanline when encoding.
+///
+/// Adaptive filtering tries every `FilterType` on a scanline and keeps the one that is expected to
+/// compress best, at the cost of filtering each scanline five times.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdaptiveFilterType {
+    /// Apply the filter chosen with `Encoder::set_filter` to every scanline.
+    NonAdaptive,
+    /// Minimum sum of the absolute values of the filtered bytes, interpreted as signed.
+    ///
+    /// This is the heuristic recommended by the PNG specification.
+    MinSum,
+    /// Minimum Shannon entropy of the filtered bytes.
+    Entropy,
+    /// Compress the output of each filter and keep the smallest, the slowest heuristic.
+    BruteForce,
+}
+
 impl FilterType {
     /// u8 -> Self. Temporary solution until Rust provides a canonical one.
     pub fn from_u8(n: u8) -> Option<FilterType> {
@@ -223,6 +241,62 @@ pub(crate) fn filter(method: FilterType, bpp: BytesPerPixel, previous: &[u8], cu
     }
 }
 
+/// Filters `current` with the filter that scores best under the heuristic and returns the filter.
+///
+/// The `scratch` buffer holds the candidates and is resized as needed.
+#[cfg(feature = "png-encoding")]
+pub(crate) fn filter_adaptive(
+    heuristic: AdaptiveFilterType,
+    bpp: BytesPerPixel,
+    previous: &[u8],
+    current: &mut [u8],
+    scratch: &mut Vec<u8>,
+) -> FilterType {
+    use self::FilterType::*;
+
+    let mut score = |method| {
+        scratch.clear();
+        scratch.extend_from_slice(current);
+        filter(method, bpp, previous, scratch);
+        match heuristic {
+            AdaptiveFilterType::NonAdaptive | AdaptiveFilterType::MinSum => scratch
+                .iter()
+                .map(|&b| f64::from(i16::from(b as i8).abs()))
+                .sum(),
+            AdaptiveFilterType::Entropy => entropy(scratch),
+            AdaptiveFilterType::BruteForce => deflate::deflate_bytes(scratch).len() as f64,
+        }
+    };
+    let mut best = (NoFilter, score(NoFilter));
+    for &method in [Sub, Up, Avg, Paeth].iter() {
+        let method_score = score(method);
+        if method_score < best.1 {
+            best = (method, method_score);
+        }
+    }
+
+    filter(best.0, bpp, previous, current);
+    best.0
+}
+
+/// The Shannon entropy of the bytes, in bits for the whole slice.
+#[cfg(feature = "png-encoding")]
+fn entropy(bytes: &[u8]) -> f64 {
+    let mut histogram = [0u32; 256];
+    for &b in bytes {
+        histogram[usize::from(b)] += 1;
+    }
+    let total = bytes.len() as f64;
+    histogram
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let count = f64::from(count);
+            -count * (count / total).log2()
+        })
+        .sum()
+}
+
 #[cfg(test)]
 mod test {
     use super::{filter, unfilter, BytesPerPixel, FilterType};
@@ -269,4 +343,47 @@ mod test {
             }
         }
     }
+
+    #[test]
+    #[cfg(feature = "png-encoding")]
+    fn adaptive_choice() {
+        use super::{filter_adaptive, AdaptiveFilterType};
+
+        let heuristics = [
+            AdaptiveFilterType::MinSum,
+            AdaptiveFilterType::Entropy,
+            AdaptiveFilterType::BruteForce,
+        ];
+        let previous: Vec<u8> = (0..64).map(|i| i * 3).collect();
+        let mut scratch = Vec::new();
+        for &heuristic in heuristics.iter() {
+            // A gradient is predicted perfectly from the left neighbour.
+            let mut current: Vec<u8> = (0..64).map(|i| 100 + i * 2).collect();
+            let chosen = filter_adaptive(
+                heuristic,
+                BytesPerPixel::One,
+                &previous,
+                &mut current,
+                &mut scratch,
+            );
+            assert_eq!(chosen, FilterType::Sub, "{:?}", heuristic);
+            unfilter(chosen, BytesPerPixel::One, &previous, &mut current).unwrap();
+            assert!(current
+                .iter()
+                .enumerate()
+                .all(|(i, &b)| b == 100 + i as u8 * 2));
+
+            // A row equal to the previous one is predicted perfectly from above.
+            let mut current = previous.clone();
+            let chosen = filter_adaptive(
+                heuristic,
+                BytesPerPixel::One,
+                &previous,
+                &mut current,
+                &mut scratch,
+            );
+            assert_eq!(chosen, FilterType::Up, "{:?}", heuristic);
+            assert!(current.iter().all(|&b| b == 0));
+        }
+    }
 }
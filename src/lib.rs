@@ -65,13 +65,17 @@ mod decoder;
 #[cfg(feature = "png-encoding")]
 mod encoder;
 mod filter;
+#[cfg(feature = "png-encoding")]
+mod reduction;
+pub mod text_metadata;
 mod traits;
 mod utils;
 
 pub use crate::common::*;
 pub use crate::decoder::{
-    Decoded, Decoder, DecodingError, Limits, OutputInfo, Reader, StreamingDecoder,
+    AnimationDecoder, Decoded, Decoder, DecodingError, FrameDelay, InterlaceHandling, Limits,
+    OutputInfo, Reader, StreamingDecoder,
 };
 #[cfg(feature = "png-encoding")]
 pub use crate::encoder::{Encoder, EncodingError, StreamWriter, Writer};
-pub use crate::filter::FilterType;
+pub use crate::filter::{AdaptiveFilterType, FilterType};
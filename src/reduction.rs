@@ -0,0 +1,346 @@
+//! Lossless reduction of the color type and bit depth of image data.
+use std::borrow::Cow;
+use std::collections::hash_map::{Entry, HashMap};
+
+use crate::common::{BitDepth, ColorType, Info};
+
+/// Convert `data` into its smallest lossless representation.
+///
+/// The color type, bit depth, palette and transparency of `info` are updated to match the
+/// returned data. Images with a color key in tRNS, with sub-byte grayscale samples, with
+/// significant bits, which describe the original color type and bit depth, or with a background
+/// color, histogram or pixel calibration, which refer to the samples or palette of the image, are
+/// kept as they are.
+pub(crate) fn reduce<'a>(info: &mut Info, data: &'a [u8]) -> Cow<'a, [u8]> {
+    if info.significant_bits.is_some()
+        || info.background_color.is_some()
+        || info.histogram.is_some()
+        || info.pixel_calibration.is_some()
+    {
+        return Cow::Borrowed(data);
+    }
+    match info.color_type {
+        ColorType::Indexed => reduce_indexed(info, data),
+        _ if info.trns.is_some() || (info.bit_depth as u8) < 8 => Cow::Borrowed(data),
+        _ => reduce_truecolor(info, data),
+    }
+}
+
+/// Lower the bit depth of an indexed image to the smallest one holding all used indices.
+fn reduce_indexed<'a>(info: &mut Info, data: &'a [u8]) -> Cow<'a, [u8]> {
+    let width = info.width as usize;
+    let depth = info.bit_depth as u8;
+    let row_len = info.raw_row_length() - 1;
+    let max_index = data
+        .chunks(row_len)
+        .flat_map(|row| samples(row, depth, width))
+        .max()
+        .unwrap_or(0);
+    let target = depth_for(usize::from(max_index) + 1);
+    if target >= depth {
+        return Cow::Borrowed(data);
+    }
+
+    let used = usize::from(max_index) + 1;
+    if let Some(palette) = &mut info.palette {
+        palette.truncate(used * 3);
+    }
+    if let Some(trns) = &mut info.trns {
+        trns.truncate(used);
+    }
+    info.bit_depth = BitDepth::from_u8(target).unwrap();
+    let indices = data
+        .chunks(row_len)
+        .flat_map(|row| samples(row, depth, width));
+    Cow::Owned(pack_rows(indices, target, width))
+}
+
+/// Drop unused channels and precision of a truecolor or grayscale image, or convert it to an
+/// indexed image if that is smaller.
+fn reduce_truecolor<'a>(info: &mut Info, data: &'a [u8]) -> Cow<'a, [u8]> {
+    let color_type = info.color_type;
+    let is_16 = info.bit_depth == BitDepth::Sixteen;
+    let has_alpha = color_type == ColorType::GrayscaleAlpha || color_type == ColorType::RGBA;
+    let width = info.width as usize;
+    let height = info.height as usize;
+    let pixels = || {
+        data.chunks_exact(info.bytes_per_pixel())
+            .map(|p| rgba(color_type, is_16, p))
+    };
+
+    let max = if is_16 { 0xFFFF } else { 0xFF };
+    let fits_8 = !is_16 || data.chunks_exact(2).all(|s| s[0] == s[1]);
+    let opaque = !has_alpha || pixels().all(|p| p[3] == max);
+    let is_gray = color_type == ColorType::Grayscale || color_type == ColorType::GrayscaleAlpha;
+    // An ICC profile describes either a grayscale or a color space, which must be kept.
+    let gray = match info.icc_profile {
+        Some(_) => is_gray,
+        None => pixels().all(|p| p[0] == p[1] && p[1] == p[2]),
+    };
+
+    let target_color = match (gray, opaque) {
+        (true, true) => ColorType::Grayscale,
+        (true, false) => ColorType::GrayscaleAlpha,
+        (false, true) => ColorType::RGB,
+        (false, false) => ColorType::RGBA,
+    };
+    let to_8 = |v: u16| if is_16 { (v >> 8) as u8 } else { v as u8 };
+    let target_depth = match target_color {
+        _ if !fits_8 => 16,
+        ColorType::Grayscale => [1, 2, 4]
+            .iter()
+            .cloned()
+            .find(|&depth| {
+                let step = 0xFF / ((1u8 << depth) - 1);
+                pixels().all(|p| to_8(p[0]) % step == 0)
+            })
+            .unwrap_or(8),
+        _ => 8,
+    };
+    let truecolor_size = height * row_bytes(width, target_color.samples() * target_depth);
+
+    let mut palette = HashMap::new();
+    let mut entries = Vec::new();
+    if fits_8 && !(is_gray && info.icc_profile.is_some()) {
+        for p in pixels() {
+            let color = [to_8(p[0]), to_8(p[1]), to_8(p[2]), to_8(p[3])];
+            if let Entry::Vacant(entry) = palette.entry(color) {
+                if entries.len() == 256 {
+                    entries.clear();
+                    break;
+                }
+                entry.insert(0);
+                entries.push(color);
+            }
+        }
+    }
+
+    if !entries.is_empty() {
+        // Transparent entries go first to keep the tRNS chunk short.
+        entries.sort_by_key(|color| color[3] == 0xFF);
+        let transparent = entries.iter().filter(|color| color[3] != 0xFF).count();
+        let index_depth = depth_for(entries.len());
+        // Each of PLTE and tRNS has a chunk overhead of 12 bytes.
+        let indexed_size = height * row_bytes(width, usize::from(index_depth))
+            + 12
+            + entries.len() * 3
+            + if transparent > 0 { 12 + transparent } else { 0 };
+        if indexed_size < truecolor_size {
+            for (index, color) in entries.iter().enumerate() {
+                palette.insert(*color, index as u8);
+            }
+            let indices =
+                pixels().map(|p| palette[&[to_8(p[0]), to_8(p[1]), to_8(p[2]), to_8(p[3])]]);
+            info.palette = Some(entries.iter().flat_map(|c| c[..3].to_vec()).collect());
+            info.trns = if transparent > 0 {
+                Some(entries[..transparent].iter().map(|c| c[3]).collect())
+            } else {
+                None
+            };
+            info.color_type = ColorType::Indexed;
+            info.bit_depth = BitDepth::from_u8(index_depth).unwrap();
+            return Cow::Owned(pack_rows(indices, index_depth, width));
+        }
+    }
+
+    if target_color == color_type && target_depth == info.bit_depth as usize {
+        return Cow::Borrowed(data);
+    }
+
+    let channels: &[usize] = match target_color {
+        ColorType::Grayscale => &[0],
+        ColorType::GrayscaleAlpha => &[0, 3],
+        ColorType::RGB => &[0, 1, 2],
+        _ => &[0, 1, 2, 3],
+    };
+    let reduced = match target_depth {
+        16 => pixels()
+            .flat_map(|p| {
+                channels
+                    .iter()
+                    .flat_map(move |&c| p[c].to_be_bytes().to_vec())
+            })
+            .collect(),
+        8 => pixels()
+            .flat_map(|p| channels.iter().map(move |&c| to_8(p[c])))
+            .collect(),
+        depth => {
+            let step = 0xFF / ((1u8 << depth) - 1);
+            let values = pixels().map(|p| to_8(p[0]) / step);
+            pack_rows(values, depth as u8, width)
+        }
+    };
+    if gray {
+        // A suggested palette is only allowed for truecolor images.
+        info.palette = None;
+    }
+    info.color_type = target_color;
+    info.bit_depth = BitDepth::from_u8(target_depth as u8).unwrap();
+    Cow::Owned(reduced)
+}
+
+/// The samples of a pixel as RGBA, with an opaque alpha for color types without alpha.
+fn rgba(color_type: ColorType, is_16: bool, pixel: &[u8]) -> [u16; 4] {
+    let sample = |i: usize| {
+        if is_16 {
+            u16::from_be_bytes([pixel[2 * i], pixel[2 * i + 1]])
+        } else {
+            u16::from(pixel[i])
+        }
+    };
+    let opaque = if is_16 { 0xFFFF } else { 0xFF };
+    match color_type {
+        ColorType::Grayscale => [sample(0), sample(0), sample(0), opaque],
+        ColorType::GrayscaleAlpha => [sample(0), sample(0), sample(0), sample(1)],
+        ColorType::RGB => [sample(0), sample(1), sample(2), opaque],
+        ColorType::RGBA => [sample(0), sample(1), sample(2), sample(3)],
+        ColorType::Indexed => unreachable!("Indexed images are reduced separately"),
+    }
+}
+
+/// The smallest bit depth of an index into a palette of `entries` entries.
+fn depth_for(entries: usize) -> u8 {
+    match entries {
+        0..=2 => 1,
+        3..=4 => 2,
+        5..=16 => 4,
+        _ => 8,
+    }
+}
+
+/// The number of bytes of a row of `width` pixels of `bits_pp` bits each.
+fn row_bytes(width: usize, bits_pp: usize) -> usize {
+    (width * bits_pp + 7) >> 3
+}
+
+/// The first `count` samples of a row of samples with 8 bits or less.
+fn samples(row: &[u8], depth: u8, count: usize) -> impl Iterator<Item = u8> + '_ {
+    let depth = usize::from(depth);
+    let mask = ((1u16 << depth) - 1) as u8;
+    (0..count).map(move |i| {
+        let bit = i * depth;
+        (row[bit / 8] >> (8 - depth - bit % 8)) & mask
+    })
+}
+
+/// Pack samples into rows of `width` samples of `depth` bits each.
+fn pack_rows(values: impl Iterator<Item = u8>, depth: u8, width: usize) -> Vec<u8> {
+    let depth = usize::from(depth);
+    let row_len = row_bytes(width, depth);
+    let mut data = Vec::new();
+    for (i, value) in values.enumerate() {
+        let column = i % width;
+        if column == 0 {
+            data.resize(data.len() + row_len, 0);
+        }
+        let row_start = data.len() - row_len;
+        let bit = column * depth;
+        data[row_start + bit / 8] |= value << (8 - depth - bit % 8);
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reduced(info: Info, data: &[u8]) -> (Info, Vec<u8>) {
+        let mut info = info;
+        let data = reduce(&mut info, data).into_owned();
+        (info, data)
+    }
+
+    fn image(width: u32, height: u32, color_type: ColorType, bit_depth: BitDepth) -> Info {
+        Info {
+            width,
+            height,
+            color_type,
+            bit_depth,
+            ..Info::default()
+        }
+    }
+
+    #[test]
+    fn opaque_gray_rgba() {
+        let data = [
+            0, 0, 0, 255, 85, 85, 85, 255, 170, 170, 170, 255, 255, 255, 255, 255,
+        ];
+        let (info, data) = reduced(image(2, 2, ColorType::RGBA, BitDepth::Eight), &data);
+        assert_eq!(info.color_type, ColorType::Grayscale);
+        assert_eq!(info.bit_depth, BitDepth::Two);
+        assert_eq!(data, [0b0001_0000, 0b1011_0000]);
+        assert_eq!(info.palette, None);
+    }
+
+    #[test]
+    fn icc_profile_keeps_color_space() {
+        let icc_profile = Some(crate::common::IccProfile {
+            name: "sRGB".to_string(),
+            profile: vec![],
+        });
+        let data: Vec<u8> = (0..64)
+            .flat_map(|i| vec![i * 4, i * 4, i * 4, 255])
+            .collect();
+        let info = Info {
+            icc_profile: icc_profile.clone(),
+            ..image(8, 8, ColorType::RGBA, BitDepth::Eight)
+        };
+        let (info, _) = reduced(info, &data);
+        assert_eq!(info.color_type, ColorType::RGB);
+
+        let data = [0; 64];
+        let info = Info {
+            icc_profile,
+            ..image(8, 8, ColorType::Grayscale, BitDepth::Eight)
+        };
+        let (info, _) = reduced(info, &data);
+        assert_eq!(info.color_type, ColorType::Grayscale);
+        assert_eq!(info.bit_depth, BitDepth::One);
+    }
+
+    #[test]
+    fn few_colors_to_indexed() {
+        let colors = [[255, 0, 0, 255], [0, 0, 255, 128]];
+        let data: Vec<u8> = (0..64).flat_map(|i| colors[i % 3 / 2].to_vec()).collect();
+        let (info, data) = reduced(image(8, 8, ColorType::RGBA, BitDepth::Eight), &data);
+        assert_eq!(info.color_type, ColorType::Indexed);
+        assert_eq!(info.bit_depth, BitDepth::One);
+        assert_eq!(info.palette, Some(vec![0, 0, 255, 255, 0, 0]));
+        assert_eq!(info.trns, Some(vec![128]));
+        assert_eq!(data[..3], [0b1101_1011, 0b0110_1101, 0b1011_0110]);
+
+        // The palette does not pay off for a tiny image.
+        let data = [255, 0, 0, 255, 0, 0, 255, 128];
+        let (info, _) = reduced(image(2, 1, ColorType::RGBA, BitDepth::Eight), &data);
+        assert_eq!(info.color_type, ColorType::RGBA);
+    }
+
+    #[test]
+    fn sixteen_to_eight_bit() {
+        let data: Vec<u8> = (0..48).map(|i| i / 2).collect();
+        let (info, reduced_data) = reduced(image(4, 2, ColorType::RGB, BitDepth::Sixteen), &data);
+        assert_eq!(info.color_type, ColorType::RGB);
+        assert_eq!(info.bit_depth, BitDepth::Eight);
+        assert_eq!(reduced_data, (0..24).collect::<Vec<u8>>());
+
+        let data: Vec<u8> = (0..48).collect();
+        let (info, reduced_data) = reduced(image(4, 2, ColorType::RGB, BitDepth::Sixteen), &data);
+        assert_eq!(info.color_type, ColorType::RGB);
+        assert_eq!(info.bit_depth, BitDepth::Sixteen);
+        assert_eq!(reduced_data, data);
+    }
+
+    #[test]
+    fn indexed_depth() {
+        let info = Info {
+            palette: Some((0..30).collect()),
+            trns: Some(vec![0; 10]),
+            ..image(3, 1, ColorType::Indexed, BitDepth::Eight)
+        };
+        let (info, data) = reduced(info, &[2, 0, 1]);
+        assert_eq!(info.bit_depth, BitDepth::Two);
+        assert_eq!(info.palette, Some((0..9).collect()));
+        assert_eq!(info.trns, Some(vec![0; 3]));
+        assert_eq!(data, [0b1000_0100]);
+    }
+}
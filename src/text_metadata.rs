@@ -0,0 +1,380 @@
+//! # Text chunks (tEXt/zTXt/iTXt) structs and functions
+//!
+//! The [PNG spec](https://www.w3.org/TR/2003/REC-PNG-20031110/#11textinfo) optionally allows for
+//! embedded text chunks in the file. They may appear either before or after the image data
+//! chunks. There are three kinds of text chunks.
+//!  -   `tEXt`: This has a `keyword` and `text` field, and is ISO 8859-1 encoded.
+//!  -   `zTXt`: This is semantically the same as `tEXt`, i.e. with the same fields and
+//!      encoding, but the `text` field is compressed before being written into the PNG file.
+//!  -   `iTXt`: This chunk allows for its `text` field to be any valid UTF-8, and supports
+//!      compression of the text field as well.
+//!
+//! The `ISO 8859-1` encoding technically doesn't allow any control characters
+//! to be used, but in practice these values are encountered anyway. This can
+//! either be the extended `ISO-8859-1` encoding with control characters or the
+//! `Windows-1252` encoding. This crate assumes the `ISO-8859-1` encoding is
+//! used.
+//!
+//! ## Reading text chunks
+//!
+//! All text chunks found before the image data are available in the `Info` returned by
+//! `Reader::info` once `Decoder::read_info` has been called. Text chunks following the image data
+//! are only seen after calling `Reader::finish`. Compressed text is inflated while decoding, its
+//! size is bounded by the `Limits` of the decoder.
+//!
+//! ```
+//! use std::fs::File;
+//! let decoder = png::Decoder::new(File::open("tests/pngsuite/ctzn0g04.png").unwrap());
+//! let (info, mut reader) = decoder.read_info().unwrap();
+//! let mut buf = vec![0; info.buffer_size()];
+//! reader.next_frame(&mut buf).unwrap();
+//! reader.finish().unwrap();
+//!
+//! for text_chunk in &reader.info().uncompressed_latin1_text {
+//!     println!("{:?}: {:?}", text_chunk.keyword, text_chunk.text);
+//! }
+//! for text_chunk in &reader.info().compressed_latin1_text {
+//!     println!("{:?}: {:?}", text_chunk.keyword, text_chunk.text);
+//! }
+//! ```
+//!
+//! ## Writing text chunks
+//!
+//! The `Encoder` offers `add_text_chunk`, `add_ztxt_chunk` and `add_itxt_chunk` to register text
+//! that is written along with the header, before any image data. Text chunks of any kind can also
+//! be written at an arbitrary point, for example after the image data, with
+//! `Writer::write_text_chunk`. Keywords are validated and text is compressed when writing.
+//!
+//! ```
+//! # #[cfg(feature = "png-encoding")] {
+//! use png::text_metadata::ITXtChunk;
+//!
+//! let mut data = Vec::new();
+//! let mut encoder = png::Encoder::new(&mut data, 1, 1);
+//! encoder.add_text_chunk("Title".to_string(), "A single pixel".to_string()).unwrap();
+//! let mut writer = encoder.write_header().unwrap();
+//! writer.write_image_data(&[0]).unwrap();
+//!
+//! let mut provenance = ITXtChunk::new("Source", "Rendered by the asset pipeline");
+//! provenance.compressed = true;
+//! writer.write_text_chunk(&provenance).unwrap();
+//! # }
+//! ```
+
+#[cfg(feature = "png-encoding")]
+use crate::chunk;
+use crate::decoder::DecodingError;
+#[cfg(feature = "png-encoding")]
+use crate::encoder::{write_chunk, EncodingError};
+
+#[cfg(feature = "png-encoding")]
+use std::io::Write;
+
+use miniz_oxide::inflate::{decompress_to_vec_zlib_with_limit, TINFLStatus};
+
+/// Decodes a slice of bytes as ISO 8859-1 into a string.
+pub(crate) fn decode_iso_8859_1(text: &[u8]) -> String {
+    text.iter().map(|&b| b as char).collect()
+}
+
+/// Checks the length restriction on keywords shared by all text chunks.
+fn validate_keyword_length(keyword: &[u8]) -> Result<(), DecodingError> {
+    if keyword.is_empty() || keyword.len() > 79 {
+        return Err(DecodingError::Format(
+            format!("invalid text keyword length ({})", keyword.len()).into(),
+        ));
+    }
+    Ok(())
+}
+
+/// Inflates the zlib stream of a compressed chunk, producing at most `limit` bytes.
+pub(crate) fn decompress_zlib(data: &[u8], limit: usize) -> Result<Vec<u8>, DecodingError> {
+    match decompress_to_vec_zlib_with_limit(data, limit) {
+        Ok(text) => Ok(text),
+        Err(TINFLStatus::HasMoreOutput) => Err(DecodingError::LimitsExceeded),
+        Err(_) => Err(DecodingError::CorruptFlateStream),
+    }
+}
+
+/// Checks that the keyword consists of 1-79 printable Latin-1 characters without leading,
+/// trailing or consecutive spaces and returns its encoded form.
+#[cfg(feature = "png-encoding")]
+pub(crate) fn encode_keyword(keyword: &str) -> Result<Vec<u8>, EncodingError> {
+    let encoded = encode_iso_8859_1(keyword)
+        .ok_or_else(|| EncodingError::Format("text keyword is not Latin-1".into()))?;
+    if encoded.is_empty() || encoded.len() > 79 {
+        return Err(EncodingError::Format(
+            format!("invalid text keyword length ({})", encoded.len()).into(),
+        ));
+    }
+    if encoded
+        .iter()
+        .any(|&b| !(b == b' ' || b.is_ascii_graphic() || b >= 161))
+    {
+        return Err(EncodingError::Format(
+            "text keyword contains non-printable characters".into(),
+        ));
+    }
+    if encoded.starts_with(b" ") || encoded.ends_with(b" ") || keyword.contains("  ") {
+        return Err(EncodingError::Format(
+            "text keyword contains leading, trailing or consecutive spaces".into(),
+        ));
+    }
+    Ok(encoded)
+}
+
+/// Encodes a string as ISO 8859-1, returns `None` if it contains other characters.
+#[cfg(feature = "png-encoding")]
+pub(crate) fn encode_iso_8859_1(text: &str) -> Option<Vec<u8>> {
+    text.chars()
+        .map(|c| std::convert::TryFrom::try_from(u32::from(c)).ok())
+        .collect()
+}
+
+/// Encodes the text of a tEXt or zTXt chunk, which has to be Latin-1 without null characters.
+#[cfg(feature = "png-encoding")]
+fn encode_latin1_text(text: &str, chunk_name: &str) -> Result<Vec<u8>, EncodingError> {
+    let encoded = encode_iso_8859_1(text).ok_or_else(|| {
+        EncodingError::Format(format!("{} text is not Latin-1", chunk_name).into())
+    })?;
+    if encoded.contains(&0) {
+        return Err(EncodingError::Format(
+            format!("{} text contains a null character", chunk_name).into(),
+        ));
+    }
+    Ok(encoded)
+}
+
+/// A text chunk that can be written by the encoder.
+#[cfg(feature = "png-encoding")]
+pub trait EncodableTextChunk {
+    /// Validates the chunk and writes it to `w`, compressing the text if required.
+    fn encode<W: Write>(&self, w: &mut W) -> Result<(), EncodingError>;
+}
+
+/// Struct representing a tEXt chunk
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TEXtChunk {
+    /// Keyword field of the tEXt chunk. Needs to be between 1-79 bytes when encoded as Latin-1.
+    pub keyword: String,
+    /// Text field of tEXt chunk. Can be at most 2GB.
+    pub text: String,
+}
+
+impl TEXtChunk {
+    /// Constructs a new tEXt chunk.
+    pub fn new(keyword: impl Into<String>, text: impl Into<String>) -> Self {
+        Self {
+            keyword: keyword.into(),
+            text: text.into(),
+        }
+    }
+
+    /// Decodes the keyword and text using Latin-1 decoding.
+    pub(crate) fn decode(keyword_slice: &[u8], text_slice: &[u8]) -> Result<Self, DecodingError> {
+        validate_keyword_length(keyword_slice)?;
+        Ok(Self {
+            keyword: decode_iso_8859_1(keyword_slice),
+            text: decode_iso_8859_1(text_slice),
+        })
+    }
+
+    /// Checks that the chunk can be encoded without writing it.
+    #[cfg(feature = "png-encoding")]
+    pub(crate) fn validate(&self) -> Result<(), EncodingError> {
+        self.encoded_data().map(drop)
+    }
+
+    #[cfg(feature = "png-encoding")]
+    fn encoded_data(&self) -> Result<Vec<u8>, EncodingError> {
+        let mut data = encode_keyword(&self.keyword)?;
+        data.push(0);
+        data.extend(encode_latin1_text(&self.text, "tEXt")?);
+        Ok(data)
+    }
+}
+
+#[cfg(feature = "png-encoding")]
+impl EncodableTextChunk for TEXtChunk {
+    fn encode<W: Write>(&self, w: &mut W) -> Result<(), EncodingError> {
+        write_chunk(w, chunk::tEXt, &self.encoded_data()?)
+    }
+}
+
+/// Struct representing a zTXt chunk
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ZTXtChunk {
+    /// Keyword field of the zTXt chunk. Needs to be between 1-79 bytes when encoded as Latin-1.
+    pub keyword: String,
+    /// Text field of the zTXt chunk, after decompression.
+    pub text: String,
+}
+
+impl ZTXtChunk {
+    /// Creates a new zTXt chunk.
+    pub fn new(keyword: impl Into<String>, text: impl Into<String>) -> Self {
+        Self {
+            keyword: keyword.into(),
+            text: text.into(),
+        }
+    }
+
+    /// Decodes the keyword and inflates the compressed text, producing at most `limit` bytes.
+    pub(crate) fn decode(
+        keyword_slice: &[u8],
+        compression_method: u8,
+        text_slice: &[u8],
+        limit: usize,
+    ) -> Result<Self, DecodingError> {
+        validate_keyword_length(keyword_slice)?;
+        if compression_method != 0 {
+            return Err(DecodingError::Format(
+                format!("unknown zTXt compression method ({})", compression_method).into(),
+            ));
+        }
+        let text = decompress_zlib(text_slice, limit)?;
+        Ok(Self {
+            keyword: decode_iso_8859_1(keyword_slice),
+            text: decode_iso_8859_1(&text),
+        })
+    }
+
+    /// Checks that the chunk can be encoded without writing it.
+    #[cfg(feature = "png-encoding")]
+    pub(crate) fn validate(&self) -> Result<(), EncodingError> {
+        encode_keyword(&self.keyword)?;
+        encode_latin1_text(&self.text, "zTXt").map(drop)
+    }
+}
+
+#[cfg(feature = "png-encoding")]
+impl EncodableTextChunk for ZTXtChunk {
+    fn encode<W: Write>(&self, w: &mut W) -> Result<(), EncodingError> {
+        let mut data = encode_keyword(&self.keyword)?;
+        // Null separator and compression method, only deflate is defined.
+        data.extend_from_slice(&[0, 0]);
+        let text = encode_latin1_text(&self.text, "zTXt")?;
+        data.extend(deflate::deflate_bytes_zlib(&text));
+        write_chunk(w, chunk::zTXt, &data)
+    }
+}
+
+/// Struct encoding an iTXt chunk
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ITXtChunk {
+    /// The keyword field. This needs to be between 1-79 bytes when encoded as Latin-1.
+    pub keyword: String,
+    /// Indicates whether the text was (or will be) stored compressed.
+    pub compressed: bool,
+    /// A hyphen separated list of languages that the keyword is translated to. This is ASCII-7 encoded.
+    pub language_tag: String,
+    /// Translated keyword. This is UTF-8 encoded.
+    pub translated_keyword: String,
+    /// Text field of iTXt chunk, after decompression if it was compressed. This is UTF-8 encoded.
+    pub text: String,
+}
+
+impl ITXtChunk {
+    /// Constructs a new iTXt chunk. Leaves all but keyword and text to default values.
+    pub fn new(keyword: impl Into<String>, text: impl Into<String>) -> Self {
+        Self {
+            keyword: keyword.into(),
+            compressed: false,
+            language_tag: "".to_string(),
+            translated_keyword: "".to_string(),
+            text: text.into(),
+        }
+    }
+
+    /// Decodes all fields of the chunk, inflating the text if it is compressed.
+    pub(crate) fn decode(
+        keyword_slice: &[u8],
+        compression_flag: u8,
+        compression_method: u8,
+        language_tag_slice: &[u8],
+        translated_keyword_slice: &[u8],
+        text_slice: &[u8],
+        limit: usize,
+    ) -> Result<Self, DecodingError> {
+        validate_keyword_length(keyword_slice)?;
+        let keyword = decode_iso_8859_1(keyword_slice);
+
+        let compressed = match compression_flag {
+            0 => false,
+            1 => true,
+            n => {
+                return Err(DecodingError::Format(
+                    format!("invalid iTXt compression flag ({})", n).into(),
+                ))
+            }
+        };
+        if compressed && compression_method != 0 {
+            return Err(DecodingError::Format(
+                format!("unknown iTXt compression method ({})", compression_method).into(),
+            ));
+        }
+
+        let language_tag = if language_tag_slice.is_ascii() {
+            decode_iso_8859_1(language_tag_slice)
+        } else {
+            return Err(DecodingError::Format(
+                "iTXt language tag is not ASCII".into(),
+            ));
+        };
+        let translated_keyword = String::from_utf8(translated_keyword_slice.to_vec())
+            .map_err(|_| DecodingError::Format("iTXt translated keyword is not UTF-8".into()))?;
+
+        let text = if compressed {
+            decompress_zlib(text_slice, limit)?
+        } else {
+            text_slice.to_vec()
+        };
+        let text = String::from_utf8(text)
+            .map_err(|_| DecodingError::Format("iTXt text is not UTF-8".into()))?;
+
+        Ok(Self {
+            keyword,
+            compressed,
+            language_tag,
+            translated_keyword,
+            text,
+        })
+    }
+
+    /// Checks that the chunk can be encoded without writing it.
+    #[cfg(feature = "png-encoding")]
+    pub(crate) fn validate(&self) -> Result<(), EncodingError> {
+        encode_keyword(&self.keyword)?;
+        if !self.language_tag.is_ascii() {
+            return Err(EncodingError::Format(
+                "iTXt language tag is not ASCII".into(),
+            ));
+        }
+        if self.language_tag.contains('\0') || self.translated_keyword.contains('\0') {
+            return Err(EncodingError::Format(
+                "iTXt language tag or translated keyword contains a null character".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "png-encoding")]
+impl EncodableTextChunk for ITXtChunk {
+    fn encode<W: Write>(&self, w: &mut W) -> Result<(), EncodingError> {
+        self.validate()?;
+        let mut data = encode_keyword(&self.keyword)?;
+        // Null separator, compression flag and the compression method which is always deflate.
+        data.extend_from_slice(&[0, self.compressed as u8, 0]);
+        data.extend_from_slice(self.language_tag.as_bytes());
+        data.push(0);
+        data.extend_from_slice(self.translated_keyword.as_bytes());
+        data.push(0);
+        if self.compressed {
+            data.extend(deflate::deflate_bytes_zlib(self.text.as_bytes()));
+        } else {
+            data.extend_from_slice(self.text.as_bytes());
+        }
+        write_chunk(w, chunk::iTXt, &data)
+    }
+}
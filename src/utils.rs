@@ -1,4 +1,5 @@
 //! Utility functions
+use std::cmp::min;
 use std::iter::{repeat, StepBy};
 use std::ops::Range;
 
@@ -166,14 +167,18 @@ impl Iterator for Adam7Iterator {
     }
 }
 
-fn subbyte_pixels<'a>(scanline: &'a [u8], bits_pp: usize) -> impl Iterator<Item = u8> + 'a {
+fn subbyte_pixels<'a>(
+    scanline: &'a [u8],
+    bits_pp: usize,
+    lsb_first: bool,
+) -> impl Iterator<Item = u8> + 'a {
     (0..scanline.len() * 8)
         .step_by(bits_pp)
         .map(move |bit_idx| {
             let byte_idx = bit_idx / 8;
 
-            // sub-byte samples start in the high-order bits
-            let rem = 8 - bit_idx % 8 - bits_pp;
+            // sub-byte samples start in the high-order bits, unless packed the other way around
+            let rem = pixel_shift(bit_idx, bits_pp, lsb_first);
 
             match bits_pp {
                 // evenly divides bytes
@@ -185,15 +190,18 @@ fn subbyte_pixels<'a>(scanline: &'a [u8], bits_pp: usize) -> impl Iterator<Item
         })
 }
 
-/// Given pass, image width, and line number, produce an iterator of bit positions of pixels to copy
-/// from the input scanline to the image buffer.
-fn expand_adam7_bits(
-    pass: u8,
-    width: usize,
-    line_no: usize,
-    bits_pp: usize,
-) -> StepBy<Range<usize>> {
-    let (line_mul, line_off, samp_mul, samp_off) = match pass {
+/// The shift of the sub-byte pixel starting at bit `bit_idx` of a line within its byte.
+fn pixel_shift(bit_idx: usize, bits_pp: usize, lsb_first: bool) -> usize {
+    if lsb_first {
+        bit_idx % 8
+    } else {
+        8 - bit_idx % 8 - bits_pp
+    }
+}
+
+/// The line multiplier and offset and the sample multiplier and offset of an Adam 7 pass.
+fn adam7_layout(pass: u8) -> (usize, usize, usize, usize) {
+    match pass {
         1 => (8, 0, 8, 0),
         2 => (8, 0, 8, 4),
         3 => (8, 4, 4, 0),
@@ -202,7 +210,33 @@ fn expand_adam7_bits(
         6 => (2, 0, 2, 1),
         7 => (2, 1, 1, 0),
         _ => panic!("Adam7 pass out of range: {}", pass),
-    };
+    }
+}
+
+/// The width and height of the block each pixel of an Adam 7 pass stands for, which is also the
+/// spacing of the pixels decoded after the pass.
+pub fn adam7_block(pass: u8) -> (usize, usize) {
+    match pass {
+        1 => (8, 8),
+        2 => (4, 8),
+        3 => (4, 4),
+        4 => (2, 4),
+        5 => (2, 2),
+        6 => (1, 2),
+        7 => (1, 1),
+        _ => panic!("Adam7 pass out of range: {}", pass),
+    }
+}
+
+/// Given pass, image width, and line number, produce an iterator of bit positions of pixels to copy
+/// from the input scanline to the image buffer.
+fn expand_adam7_bits(
+    pass: u8,
+    width: usize,
+    line_no: usize,
+    bits_pp: usize,
+) -> StepBy<Range<usize>> {
+    let (line_mul, line_off, samp_mul, samp_off) = adam7_layout(pass);
 
     // the equivalent line number in progressive scan
     let prog_line = line_mul * line_no + line_off;
@@ -215,7 +249,8 @@ fn expand_adam7_bits(
     (start..stop).step_by(bits_pp * samp_mul)
 }
 
-/// Expands an Adam 7 pass
+/// Expands an Adam 7 pass, sub-byte pixels are packed starting at the low-order bits with
+/// `lsb_first`.
 pub fn expand_pass(
     img: &mut [u8],
     width: u32,
@@ -223,6 +258,7 @@ pub fn expand_pass(
     pass: u8,
     line_no: u32,
     bits_pp: u8,
+    lsb_first: bool,
 ) {
     let width = width as usize;
     let line_no = line_no as usize;
@@ -236,9 +272,10 @@ pub fn expand_pass(
     let bit_indices = expand_adam7_bits(pass, width, line_no, bits_pp);
 
     if bits_pp < 8 {
-        for (pos, px) in bit_indices.zip(subbyte_pixels(scanline, bits_pp)) {
-            let rem = 8 - pos % 8 - bits_pp;
-            img[pos / 8] |= px << rem as u8;
+        let mask = (1u8 << bits_pp) - 1;
+        for (pos, px) in bit_indices.zip(subbyte_pixels(scanline, bits_pp, lsb_first)) {
+            let rem = pixel_shift(pos, bits_pp, lsb_first);
+            img[pos / 8] = img[pos / 8] & !(mask << rem) | px << rem;
         }
     } else {
         let bytes_pp = bits_pp / 8;
@@ -251,6 +288,184 @@ pub fn expand_pass(
     }
 }
 
+/// Copies the pixel at index `src` of a line to index `dst` of another one.
+fn copy_pixel(from: &[u8], src: usize, to: &mut [u8], dst: usize, bits_pp: usize, lsb_first: bool) {
+    if bits_pp < 8 {
+        let mask = (1u8 << bits_pp) - 1;
+        let src = src * bits_pp;
+        let px = (from[src / 8] >> pixel_shift(src, bits_pp, lsb_first)) & mask;
+        let dst = dst * bits_pp;
+        let rem = pixel_shift(dst, bits_pp, lsb_first);
+        to[dst / 8] = to[dst / 8] & !(mask << rem) | px << rem;
+    } else {
+        let bytes_pp = bits_pp / 8;
+        let px = &from[src * bytes_pp..(src + 1) * bytes_pp];
+        to[dst * bytes_pp..(dst + 1) * bytes_pp].copy_from_slice(px);
+    }
+}
+
+/// Copies the first `bits` bits of a line to `out`, keeping the bits that follow them in the
+/// last byte of `out`.
+pub fn copy_bits(line: &[u8], bits: usize, out: &mut [u8], lsb_first: bool) {
+    let bytes = bits / 8;
+    out[..bytes].copy_from_slice(&line[..bytes]);
+    let rem = bits % 8;
+    if rem > 0 {
+        let keep = if lsb_first { 0xFF << rem } else { 0xFF >> rem };
+        out[bytes] = out[bytes] & keep | line[bytes] & !keep;
+    }
+}
+
+/// The line of the image that line `line_no` of an Adam 7 pass belongs to.
+pub fn adam7_line(pass: u8, line_no: u32) -> usize {
+    let (line_mul, line_off, _, _) = adam7_layout(pass);
+    line_mul * line_no as usize + line_off
+}
+
+/// Expands an Adam 7 pass line into the image line it belongs to, given by `adam7_line`. Sub-byte
+/// pixels are packed starting at the low-order bits with `lsb_first`.
+pub fn expand_pass_row(
+    row: &mut [u8],
+    width: u32,
+    scanline: &[u8],
+    pass: u8,
+    bits_pp: u8,
+    lsb_first: bool,
+) {
+    let (_, _, samp_mul, samp_off) = adam7_layout(pass);
+    let columns = (samp_off..width as usize).step_by(samp_mul);
+    for (i, x) in columns.enumerate() {
+        copy_pixel(scanline, i, row, x, bits_pp as usize, lsb_first);
+    }
+}
+
+/// Copies `len` pixels of a line starting at pixel `start` to the start of `out`, sub-byte pixels
+/// are packed starting at the low-order bits with `lsb_first`. The bits following the pixels in
+/// their last byte are cleared.
+pub fn crop_line(
+    line: &[u8],
+    (start, len): (usize, usize),
+    bits_pp: usize,
+    lsb_first: bool,
+    out: &mut [u8],
+) {
+    if bits_pp < 8 {
+        out[..(len * bits_pp + 7) >> 3]
+            .iter_mut()
+            .for_each(|byte| *byte = 0);
+        for i in 0..len {
+            copy_pixel(line, start + i, out, i, bits_pp, lsb_first);
+        }
+    } else {
+        let bytes_pp = bits_pp / 8;
+        out[..len * bytes_pp].copy_from_slice(&line[start * bytes_pp..(start + len) * bytes_pp]);
+    }
+}
+
+/// Expands an Adam 7 pass like `expand_pass` and also fills the block of pixels each pixel of
+/// the pass stands for until later passes decode them, for a progressive preview of the image.
+pub fn expand_pass_rect(
+    img: &mut [u8],
+    (width, height): (u32, u32),
+    scanline: &[u8],
+    pass: u8,
+    line_no: u32,
+    bits_pp: u8,
+    lsb_first: bool,
+) {
+    // pass is out of range but don't blow up
+    if pass == 0 || pass > 7 {
+        return;
+    }
+    let (block_width, block_height) = adam7_block(pass);
+    let width = width as usize;
+    let height = height as usize;
+    let bits_pp = bits_pp as usize;
+    let line_len = (width * bits_pp + 7) >> 3;
+
+    // Write the pixels and their horizontal blocks into the first line, then copy it downwards.
+    let (line_mul, line_off, samp_mul, samp_off) = adam7_layout(pass);
+    let prog_line = line_mul * line_no as usize + line_off;
+    let (line, below) = img[prog_line * line_len..].split_at_mut(line_len);
+    for (i, x) in (samp_off..width).step_by(samp_mul).enumerate() {
+        for col in x..min(x + block_width, width) {
+            copy_pixel(scanline, i, line, col, bits_pp, lsb_first);
+        }
+    }
+    let lines_below = min(prog_line + block_height, height) - prog_line - 1;
+    for copy in below.chunks_exact_mut(line_len).take(lines_below) {
+        copy.copy_from_slice(line);
+    }
+}
+
+/// Expands an Adam 7 pass line into the reduced image of the pixels decoded after `max_pass`,
+/// which is `reduced_width` pixels wide. Pixels of the line outside of that grid are skipped.
+pub fn expand_pass_reduced(
+    img: &mut [u8],
+    reduced_width: u32,
+    scanline: &[u8],
+    (pass, line_no): (u8, u32),
+    bits_pp: u8,
+    max_pass: u8,
+    lsb_first: bool,
+) {
+    let (x_step, y_step) = adam7_block(max_pass);
+    let (line_mul, line_off, samp_mul, samp_off) = adam7_layout(pass);
+    let prog_line = line_mul * line_no as usize + line_off;
+    let reduced_line = prog_line / y_step;
+    if reduced_line * y_step != prog_line {
+        return;
+    }
+    let bits_pp = bits_pp as usize;
+    let line_len = (reduced_width as usize * bits_pp + 7) >> 3;
+    let line = &mut img[reduced_line * line_len..][..line_len];
+    let xs = (samp_off..).step_by(samp_mul);
+    for (i, x) in xs
+        .take_while(|&x| x < reduced_width as usize * x_step)
+        .enumerate()
+    {
+        if x % x_step > 0 {
+            continue;
+        }
+        copy_pixel(scanline, i, line, x / x_step, bits_pp, lsb_first);
+    }
+}
+
+/// Extracts the pixels of an Adam 7 pass line from an image, the inverse of `expand_pass`.
+///
+/// The `scanline` must be of the length of the pass line and is overwritten.
+#[cfg(feature = "png-encoding")]
+pub fn extract_pass_line(
+    img: &[u8],
+    width: u32,
+    scanline: &mut [u8],
+    pass: u8,
+    line_no: u32,
+    bits_pp: u8,
+) {
+    let width = width as usize;
+    let line_no = line_no as usize;
+    let bits_pp = bits_pp as usize;
+
+    let bit_indices = expand_adam7_bits(pass, width, line_no, bits_pp);
+
+    if bits_pp < 8 {
+        let mask = (1u8 << bits_pp) - 1;
+        scanline.iter_mut().for_each(|byte| *byte = 0);
+        for (idx, pos) in bit_indices.enumerate() {
+            let px = (img[pos / 8] >> (8 - pos % 8 - bits_pp)) & mask;
+            let out = idx * bits_pp;
+            scanline[out / 8] |= px << (8 - out % 8 - bits_pp);
+        }
+    } else {
+        let bytes_pp = bits_pp / 8;
+
+        for (bitpos, px) in bit_indices.zip(scanline.chunks_mut(bytes_pp)) {
+            px.copy_from_slice(&img[bitpos / 8..bitpos / 8 + bytes_pp]);
+        }
+    }
+}
+
 #[test]
 fn test_adam7() {
     /*
@@ -279,7 +494,7 @@ fn test_adam7() {
 fn test_subbyte_pixels() {
     let scanline = &[0b10101010, 0b10101010];
 
-    let pixels = subbyte_pixels(scanline, 1).collect::<Vec<_>>();
+    let pixels = subbyte_pixels(scanline, 1, false).collect::<Vec<_>>();
     assert_eq!(pixels.len(), 16);
     assert_eq!(pixels, [1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0]);
 }
@@ -359,55 +574,55 @@ fn test_expand_pass_subbyte() {
     let width = 8;
     let bits_pp = 1;
 
-    expand_pass(&mut img, width, &[0b10000000], 1, 0, bits_pp);
+    expand_pass(&mut img, width, &[0b10000000], 1, 0, bits_pp, false);
     assert_eq!(img, [0b10000000u8, 0, 0, 0, 0, 0, 0, 0]);
 
-    expand_pass(&mut img, width, &[0b10000000], 2, 0, bits_pp);
+    expand_pass(&mut img, width, &[0b10000000], 2, 0, bits_pp, false);
     assert_eq!(img, [0b10001000u8, 0, 0, 0, 0, 0, 0, 0]);
 
-    expand_pass(&mut img, width, &[0b11000000], 3, 0, bits_pp);
+    expand_pass(&mut img, width, &[0b11000000], 3, 0, bits_pp, false);
     assert_eq!(img, [0b10001000u8, 0, 0, 0, 0b10001000, 0, 0, 0]);
 
-    expand_pass(&mut img, width, &[0b11000000], 4, 0, bits_pp);
+    expand_pass(&mut img, width, &[0b11000000], 4, 0, bits_pp, false);
     assert_eq!(img, [0b10101010u8, 0, 0, 0, 0b10001000, 0, 0, 0]);
 
-    expand_pass(&mut img, width, &[0b11000000], 4, 1, bits_pp);
+    expand_pass(&mut img, width, &[0b11000000], 4, 1, bits_pp, false);
     assert_eq!(img, [0b10101010u8, 0, 0, 0, 0b10101010, 0, 0, 0]);
 
-    expand_pass(&mut img, width, &[0b11110000], 5, 0, bits_pp);
+    expand_pass(&mut img, width, &[0b11110000], 5, 0, bits_pp, false);
     assert_eq!(img, [0b10101010u8, 0, 0b10101010, 0, 0b10101010, 0, 0, 0]);
 
-    expand_pass(&mut img, width, &[0b11110000], 5, 1, bits_pp);
+    expand_pass(&mut img, width, &[0b11110000], 5, 1, bits_pp, false);
     assert_eq!(
         img,
         [0b10101010u8, 0, 0b10101010, 0, 0b10101010, 0, 0b10101010, 0]
     );
 
-    expand_pass(&mut img, width, &[0b11110000], 6, 0, bits_pp);
+    expand_pass(&mut img, width, &[0b11110000], 6, 0, bits_pp, false);
     assert_eq!(
         img,
         [0b11111111u8, 0, 0b10101010, 0, 0b10101010, 0, 0b10101010, 0]
     );
 
-    expand_pass(&mut img, width, &[0b11110000], 6, 1, bits_pp);
+    expand_pass(&mut img, width, &[0b11110000], 6, 1, bits_pp, false);
     assert_eq!(
         img,
         [0b11111111u8, 0, 0b11111111, 0, 0b10101010, 0, 0b10101010, 0]
     );
 
-    expand_pass(&mut img, width, &[0b11110000], 6, 2, bits_pp);
+    expand_pass(&mut img, width, &[0b11110000], 6, 2, bits_pp, false);
     assert_eq!(
         img,
         [0b11111111u8, 0, 0b11111111, 0, 0b11111111, 0, 0b10101010, 0]
     );
 
-    expand_pass(&mut img, width, &[0b11110000], 6, 3, bits_pp);
+    expand_pass(&mut img, width, &[0b11110000], 6, 3, bits_pp, false);
     assert_eq!(
         [0b11111111u8, 0, 0b11111111, 0, 0b11111111, 0, 0b11111111, 0],
         img
     );
 
-    expand_pass(&mut img, width, &[0b11111111], 7, 0, bits_pp);
+    expand_pass(&mut img, width, &[0b11111111], 7, 0, bits_pp, false);
     assert_eq!(
         [
             0b11111111u8,
@@ -422,7 +637,7 @@ fn test_expand_pass_subbyte() {
         img
     );
 
-    expand_pass(&mut img, width, &[0b11111111], 7, 1, bits_pp);
+    expand_pass(&mut img, width, &[0b11111111], 7, 1, bits_pp, false);
     assert_eq!(
         [
             0b11111111u8,
@@ -437,7 +652,7 @@ fn test_expand_pass_subbyte() {
         img
     );
 
-    expand_pass(&mut img, width, &[0b11111111], 7, 2, bits_pp);
+    expand_pass(&mut img, width, &[0b11111111], 7, 2, bits_pp, false);
     assert_eq!(
         [
             0b11111111u8,
@@ -452,7 +667,7 @@ fn test_expand_pass_subbyte() {
         img
     );
 
-    expand_pass(&mut img, width, &[0b11111111], 7, 3, bits_pp);
+    expand_pass(&mut img, width, &[0b11111111], 7, 3, bits_pp, false);
     assert_eq!(
         [
             0b11111111u8,
@@ -467,3 +682,59 @@ fn test_expand_pass_subbyte() {
         img
     );
 }
+
+#[test]
+fn test_expand_pass_rect_subbyte() {
+    let mut img = [0u8; 8];
+    let size = (8, 8);
+
+    expand_pass_rect(&mut img, size, &[0b10000000], 1, 0, 1, false);
+    assert_eq!(img, [0b11111111; 8]);
+
+    expand_pass_rect(&mut img, size, &[0b00000000], 2, 0, 1, false);
+    assert_eq!(img, [0b11110000; 8]);
+
+    expand_pass_rect(&mut img, size, &[0b01000000], 3, 0, 1, false);
+    assert_eq!(
+        img,
+        [
+            0b11110000, 0b11110000, 0b11110000, 0b11110000, 0b00001111, 0b00001111, 0b00001111,
+            0b00001111
+        ]
+    );
+}
+
+#[test]
+#[cfg(feature = "png-encoding")]
+fn test_extract_pass_line_roundtrip() {
+    for &bits_pp in &[1u8, 2, 4, 8, 24] {
+        let width = 13;
+        let height = 11;
+        let line_len = (width as usize * bits_pp as usize + 7) >> 3;
+        let img: Vec<u8> = (0..line_len * height as usize)
+            .map(|i| (i * 37 + 11) as u8)
+            .collect();
+        // Padding bits at the end of each line are not part of any pass.
+        let mut expanded = vec![0; img.len()];
+        for (pass, line_no, pass_width) in Adam7Iterator::new(width, height) {
+            let mut scanline = vec![0; (pass_width as usize * bits_pp as usize + 7) >> 3];
+            extract_pass_line(&img, width, &mut scanline, pass, line_no, bits_pp);
+            expand_pass(
+                &mut expanded,
+                width,
+                &scanline,
+                pass,
+                line_no,
+                bits_pp,
+                false,
+            );
+        }
+        let used_bits = width as usize * bits_pp as usize;
+        for (expected, actual) in img.chunks(line_len).zip(expanded.chunks(line_len)) {
+            for bit in 0..used_bits {
+                let mask = 0x80 >> (bit % 8);
+                assert_eq!(expected[bit / 8] & mask, actual[bit / 8] & mask);
+            }
+        }
+    }
+}
@@ -184,6 +184,30 @@ fn apng_images() {
     })
 }
 
+#[test]
+fn apng_composited() {
+    process_images(
+        "results_apng_composited.txt",
+        &APNG_SUITES,
+        |path: PathBuf| {
+            let decoder = png::Decoder::new(File::open(&path)?);
+            let (_, reader) = decoder.read_info()?;
+            let mut animation = png::AnimationDecoder::new(reader)?;
+            let mut img_data = vec![0; animation.output_buffer_size()];
+            let num_frames = animation.info().animation_control().unwrap().num_frames;
+
+            let mut crc = Crc32::new();
+            let mut frames = 0;
+            while animation.next_frame(&mut img_data)?.is_some() {
+                crc.update(&img_data);
+                frames += 1;
+            }
+            assert_eq!(frames, num_frames);
+            Ok(crc.finalize())
+        },
+    )
+}
+
 // until rust standardizes path normalization, see https://github.com/rust-lang/rfcs/issues/2208
 fn normalize_path(path: &Path) -> PathBuf {
     let mut components = path.components().peekable();